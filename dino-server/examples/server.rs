@@ -1,5 +1,7 @@
 use anyhow::Result;
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use dino_server::{
+    start_server, FileRegistry, ProjectConfig, ServerConfig, SwappableAppRouter, TenentRouter,
+};
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::{
     fmt::Layer, layer::SubscriberExt as _, util::SubscriberInitExt as _, Layer as _,
@@ -17,8 +19,9 @@ async fn main() -> Result<()> {
 
     let router = vec![TenentRouter::new(
         "localhost",
-        SwappableAppRouter::try_new(code, config.routes)?,
+        SwappableAppRouter::try_new(code, config)?,
     )];
-    start_server(8080, router).await?;
+    let registry = FileRegistry::new(std::env::temp_dir().join("dino-registry.json"));
+    start_server(ServerConfig::from_ports(8080, None), router, &registry).await?;
     Ok(())
 }
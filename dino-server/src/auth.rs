@@ -0,0 +1,248 @@
+use std::{fmt, fs};
+
+use anyhow::{anyhow, Result};
+use axum::http::{header, HeaderMap};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use rquickjs::{Ctx, IntoJs, Value};
+use serde_json::json;
+
+use crate::{ApiKeyConfig, AppError, AuthConfig, BasicAuthConfig, JwtConfig};
+
+/// An [`AuthConfig`] with its keys loaded, checked before the handler runs.
+pub enum Authenticator {
+    Jwt(JwtAuth),
+    ApiKey(ApiKeyConfig),
+    Basic(BasicAuthConfig),
+    None,
+}
+
+pub struct JwtAuth {
+    // kid, key and its algorithm
+    keys: Vec<(Option<String>, DecodingKey, Algorithm)>,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+/// Claims of an authenticated request, passed to the handler as `req.claims`. API keys and
+/// basic auth only set `sub`, to the key name or the user name.
+#[derive(Debug, Clone)]
+pub struct Claims(pub serde_json::Value);
+
+impl Authenticator {
+    pub fn try_new(config: &AuthConfig) -> Result<Self> {
+        Ok(match config {
+            AuthConfig::Jwt(config) => Authenticator::Jwt(JwtAuth::try_new(config)?),
+            AuthConfig::ApiKey(config) => Authenticator::ApiKey(config.clone()),
+            AuthConfig::Basic(config) => Authenticator::Basic(config.clone()),
+            AuthConfig::None => Authenticator::None,
+        })
+    }
+
+    /// Verify the credentials of a request, `None` is returned when no authentication is
+    /// required.
+    pub fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Claims>, AppError> {
+        match self {
+            Authenticator::Jwt(jwt) => {
+                let token = authorization(headers, "Bearer")
+                    .ok_or_else(|| unauthorized("Bearer", "missing bearer token"))?;
+                jwt.verify(token).map(Some)
+            }
+            Authenticator::ApiKey(config) => {
+                let key = headers
+                    .get(config.header.as_str())
+                    .map(|v| v.as_bytes())
+                    .ok_or_else(|| unauthorized("ApiKey", "missing api key"))?;
+                config
+                    .keys
+                    .iter()
+                    .find(|(_, k)| constant_time_eq(k.as_bytes(), key))
+                    .map(|(name, _)| Some(Claims(json!({ "sub": name }))))
+                    .ok_or_else(|| unauthorized("ApiKey", "invalid api key"))
+            }
+            Authenticator::Basic(config) => {
+                let challenge = format!("Basic realm=\"{}\"", config.realm);
+                let credentials = authorization(headers, "Basic")
+                    .and_then(|v| STANDARD.decode(v).ok())
+                    .and_then(|v| String::from_utf8(v).ok())
+                    .ok_or_else(|| unauthorized(&challenge, "missing basic credentials"))?;
+                let (user, password) = credentials.split_once(':').unwrap_or_default();
+                match config.users.get(user) {
+                    Some(p) if constant_time_eq(p.as_bytes(), password.as_bytes()) => {
+                        Ok(Some(Claims(json!({ "sub": user }))))
+                    }
+                    _ => Err(unauthorized(&challenge, "invalid user name or password")),
+                }
+            }
+            Authenticator::None => Ok(None),
+        }
+    }
+}
+
+impl JwtAuth {
+    fn try_new(config: &JwtConfig) -> Result<Self> {
+        let mut keys = Vec::new();
+        if let Some(secret) = &config.secret {
+            keys.push((
+                None,
+                DecodingKey::from_secret(secret.as_bytes()),
+                config.algorithm,
+            ));
+        }
+        if let Some(path) = &config.key {
+            let pem = fs::read(path)?;
+            let key = match config.algorithm {
+                Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512 => DecodingKey::from_rsa_pem(&pem)?,
+                Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(&pem)?,
+                Algorithm::EdDSA => DecodingKey::from_ed_pem(&pem)?,
+                alg => return Err(anyhow!("{:?} can't be used with a public key", alg)),
+            };
+            keys.push((None, key, config.algorithm));
+        }
+        if let Some(path) = &config.jwks {
+            let jwks: JwkSet = serde_json::from_str(&fs::read_to_string(path)?)?;
+            for jwk in &jwks.keys {
+                let alg = match jwk.common.key_algorithm {
+                    Some(alg) => alg.to_string().parse()?,
+                    None => config.algorithm,
+                };
+                keys.push((jwk.common.key_id.clone(), DecodingKey::from_jwk(jwk)?, alg));
+            }
+        }
+        if keys.is_empty() {
+            return Err(anyhow!("jwt auth needs a secret, a key or a jwks file"));
+        }
+        Ok(Self {
+            keys,
+            issuer: config.issuer.clone(),
+            audience: config.audience.clone(),
+        })
+    }
+
+    fn verify(&self, token: &str) -> Result<Claims, AppError> {
+        let invalid = |e: jsonwebtoken::errors::Error| unauthorized("Bearer", &e.to_string());
+        let header = decode_header(token).map_err(invalid)?;
+        // the key named by the token, or else the first one of its algorithm
+        let (_, key, alg) = self
+            .keys
+            .iter()
+            .find(|(kid, _, _)| kid.is_some() && *kid == header.kid)
+            .or_else(|| self.keys.iter().find(|(_, _, alg)| *alg == header.alg))
+            .ok_or_else(|| unauthorized("Bearer", "no key matches the token"))?;
+        let mut validation = Validation::new(*alg);
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &self.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        let data = decode::<serde_json::Value>(token, key, &validation).map_err(invalid)?;
+        Ok(Claims(data.claims))
+    }
+}
+
+// keys and passwords are kept out of logs
+impl fmt::Debug for Authenticator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Authenticator::Jwt(_) => "Jwt",
+            Authenticator::ApiKey(_) => "ApiKey",
+            Authenticator::Basic(_) => "Basic",
+            Authenticator::None => "None",
+        };
+        f.debug_tuple("Authenticator").field(&kind).finish()
+    }
+}
+
+impl<'js> IntoJs<'js> for Claims {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        ctx.json_parse(self.0.to_string())
+    }
+}
+
+fn authorization<'a>(headers: &'a HeaderMap, scheme: &str) -> Option<&'a str> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (s, credentials) = value.split_once(' ')?;
+    s.eq_ignore_ascii_case(scheme).then(|| credentials.trim())
+}
+
+fn unauthorized(challenge: &str, reason: &str) -> AppError {
+    AppError::Unauthorized {
+        challenge: challenge.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    fn headers(name: &str, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(value).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn jwt_auth_should_verify_token() -> Result<()> {
+        let config: AuthConfig = serde_yaml::from_str("type: jwt\nsecret: secret\nissuer: dino\n")?;
+        let auth = Authenticator::try_new(&config)?;
+        let claims = json!({ "sub": "alice", "iss": "dino", "exp": u32::MAX });
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"secret"),
+        )?;
+
+        let ret = auth.authenticate(&headers("authorization", &format!("Bearer {}", token)))?;
+        assert_eq!(ret.unwrap().0["sub"], "alice");
+
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"other"),
+        )?;
+        let ret = auth.authenticate(&headers("authorization", &format!("Bearer {}", token)));
+        assert!(matches!(ret, Err(AppError::Unauthorized { .. })));
+        assert!(auth.authenticate(&HeaderMap::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn api_key_and_basic_auth_should_work() -> Result<()> {
+        let config: AuthConfig = serde_yaml::from_str("type: api_key\nkeys:\n  ci: abc\n")?;
+        let auth = Authenticator::try_new(&config)?;
+        let claims = auth.authenticate(&headers("x-api-key", "abc"))?.unwrap();
+        assert_eq!(claims.0["sub"], "ci");
+        assert!(auth.authenticate(&headers("x-api-key", "abd")).is_err());
+
+        let config: AuthConfig = serde_yaml::from_str("type: basic\nusers:\n  alice: pass\n")?;
+        let auth = Authenticator::try_new(&config)?;
+        let ok = format!("Basic {}", STANDARD.encode("alice:pass"));
+        let claims = auth.authenticate(&headers("authorization", &ok))?.unwrap();
+        assert_eq!(claims.0["sub"], "alice");
+        let bad = format!("Basic {}", STANDARD.encode("alice:nope"));
+        let Err(AppError::Unauthorized { challenge, .. }) =
+            auth.authenticate(&headers("authorization", &bad))
+        else {
+            panic!("expect a wrong password to be rejected");
+        };
+        assert_eq!(challenge, "Basic realm=\"dino\"");
+        Ok(())
+    }
+}
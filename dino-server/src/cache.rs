@@ -0,0 +1,346 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::http::{header, request::Parts, HeaderMap, HeaderValue, Method};
+use indexmap::IndexMap;
+
+use crate::{CacheConfig, Res};
+
+/// Tells whether a response was served from the cache: `hit`, `stale` or `miss`.
+pub(crate) const CACHE_STATUS_HEADER: &str = "x-cache";
+
+// statuses a shared cache may store without explicit permission
+const CACHEABLE_STATUS: [u16; 6] = [200, 203, 204, 301, 404, 410];
+
+/// In-memory cache of handler responses, honoring the `Cache-Control` set by the handler.
+/// It lives as long as the tenant's code, so a swap starts with an empty cache.
+pub struct ResponseCache {
+    config: CacheConfig,
+    // request key to the responses stored for it, one per set of `Vary` header values
+    entries: Mutex<IndexMap<String, Vec<Arc<CachedResponse>>>>,
+}
+
+pub struct CachedResponse {
+    res: Res,
+    // request headers named by `Vary`, with their values
+    vary: Vec<(String, Option<HeaderValue>)>,
+    stored: Instant,
+    ttl: Duration,
+    stale_while_revalidate: Duration,
+    revalidating: AtomicBool,
+}
+
+/// A response found in the cache.
+pub enum CacheLookup {
+    Fresh(Res, Duration),
+    /// past its ttl but within `stale-while-revalidate`. The entry is returned only to the
+    /// first request seeing it stale, which should refresh it.
+    Stale(Res, Duration, Option<Arc<CachedResponse>>),
+}
+
+#[derive(Debug, Default)]
+struct CacheControl {
+    no_store: bool,
+    no_cache: bool,
+    private: bool,
+    public: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+}
+
+impl ResponseCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(IndexMap::new()),
+        }
+    }
+
+    /// Key of a request, `None` if its response must not be cached.
+    pub fn key(&self, parts: &Parts) -> Option<String> {
+        if parts.method != Method::GET && parts.method != Method::HEAD {
+            return None;
+        }
+        if CacheControl::parse(&parts.headers).no_store {
+            return None;
+        }
+        let uri = parts.uri.path_and_query()?;
+        Some(format!("{} {}", parts.method, uri))
+    }
+
+    /// Look up the response stored for a request, unless the request asks for a fresh one.
+    pub fn get(&self, key: &str, headers: &HeaderMap) -> Option<CacheLookup> {
+        if CacheControl::parse(headers).no_cache {
+            return None;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        let list = entries.get_mut(key)?;
+        let now = Instant::now();
+        list.retain(|e| now.duration_since(e.stored) < e.ttl + e.stale_while_revalidate);
+        let entry = list.iter().find(|e| e.matches(headers))?.clone();
+        drop(entries);
+
+        let age = now.duration_since(entry.stored);
+        if age < entry.ttl {
+            return Some(CacheLookup::Fresh(entry.res.clone(), age));
+        }
+        let first = !entry.revalidating.swap(true, Ordering::AcqRel);
+        Some(CacheLookup::Stale(
+            entry.res.clone(),
+            age,
+            first.then_some(entry),
+        ))
+    }
+
+    /// Store a response if its `Cache-Control` allows a shared cache to. Responses to
+    /// `authenticated` requests need to be marked `public` or carry an `s-maxage`, those to
+    /// requests with cookies need a `Vary: Cookie`, and those setting cookies are never stored.
+    pub fn store(&self, key: String, headers: &HeaderMap, authenticated: bool, res: &Res) {
+        if !CACHEABLE_STATUS.contains(&res.status)
+            || res.body.as_ref().map(|b| b.len()).unwrap_or_default() > self.config.max_body_size
+            || res_header(res, "set-cookie").is_some()
+        {
+            return;
+        }
+        let cc = CacheControl::parse_res(res);
+        let ttl = cc.s_maxage.or(cc.max_age).unwrap_or_default();
+        let shared = !authenticated || cc.public || cc.s_maxage.is_some();
+        if cc.no_store || cc.no_cache || cc.private || ttl == 0 || !shared {
+            return;
+        }
+        let vary = res_header(res, "vary").unwrap_or_default();
+        if vary.trim() == "*" {
+            return;
+        }
+        let vary = vary
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = headers.get(name.as_str()).cloned();
+                (name, value)
+            })
+            .collect::<Vec<_>>();
+        // the response may depend on the cookies, unless it tells it doesn't
+        if headers.contains_key(header::COOKIE) && !vary.iter().any(|(name, _)| name == "cookie") {
+            return;
+        }
+        let entry = Arc::new(CachedResponse {
+            res: res.clone(),
+            vary,
+            stored: Instant::now(),
+            ttl: Duration::from_secs(ttl),
+            stale_while_revalidate: Duration::from_secs(
+                cc.stale_while_revalidate.unwrap_or_default(),
+            ),
+            revalidating: AtomicBool::new(false),
+        });
+
+        let mut entries = self.entries.lock().unwrap();
+        let list = entries.entry(key).or_default();
+        list.retain(|e| !e.same_variant(&entry));
+        list.push(entry);
+        while entries.len() > self.config.max_entries {
+            entries.shift_remove_index(0);
+        }
+    }
+}
+
+impl CachedResponse {
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get(name.as_str()) == value.as_ref())
+    }
+
+    fn same_variant(&self, other: &CachedResponse) -> bool {
+        self.vary == other.vary
+    }
+
+    /// Let another request revalidate the entry, after a refresh failed.
+    pub fn revalidation_failed(&self) {
+        self.revalidating.store(false, Ordering::Release);
+    }
+}
+
+impl CacheControl {
+    fn parse(headers: &HeaderMap) -> Self {
+        let value = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .collect::<Vec<_>>()
+            .join(",");
+        Self::parse_str(&value)
+    }
+
+    fn parse_res(res: &Res) -> Self {
+        Self::parse_str(res_header(res, "cache-control").unwrap_or_default())
+    }
+
+    fn parse_str(s: &str) -> Self {
+        let mut cc = Self::default();
+        for directive in s.split(',') {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let secs = value.and_then(|v| v.parse().ok());
+            match name.to_lowercase().as_str() {
+                "no-store" => cc.no_store = true,
+                "no-cache" => cc.no_cache = true,
+                "private" => cc.private = true,
+                "public" => cc.public = true,
+                "max-age" => cc.max_age = secs,
+                "s-maxage" => cc.s_maxage = secs,
+                "stale-while-revalidate" => cc.stale_while_revalidate = secs,
+                _ => {}
+            }
+        }
+        cc
+    }
+}
+
+fn res_header<'a>(res: &'a Res, name: &str) -> Option<&'a str> {
+    res.headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use std::collections::HashMap;
+
+    fn parts(uri: &str, headers: &[(&str, &str)]) -> Parts {
+        let mut req = Request::get(uri);
+        for (k, v) in headers {
+            req = req.header(*k, *v);
+        }
+        req.body(()).unwrap().into_parts().0
+    }
+
+    // age every entry by `secs`
+    fn backdate(cache: &ResponseCache, secs: u64) {
+        let mut entries = cache.entries.lock().unwrap();
+        for e in entries.values_mut().flatten() {
+            *e = Arc::new(CachedResponse {
+                res: e.res.clone(),
+                vary: e.vary.clone(),
+                stored: e.stored - Duration::from_secs(secs),
+                ttl: e.ttl,
+                stale_while_revalidate: e.stale_while_revalidate,
+                revalidating: AtomicBool::new(e.revalidating.load(Ordering::Acquire)),
+            });
+        }
+    }
+
+    fn res(cache_control: &str, body: &str) -> Res {
+        Res {
+            status: 200,
+            headers: HashMap::from([
+                ("Cache-Control".to_string(), cache_control.to_string()),
+                ("Vary".to_string(), "accept-language".to_string()),
+            ]),
+            body: Some(body.to_string()),
+        }
+    }
+
+    #[test]
+    fn response_cache_should_honor_cache_control_and_vary() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let en = parts("/api/hello?x=1", &[("accept-language", "en")]);
+        let fr = parts("/api/hello?x=1", &[("accept-language", "fr")]);
+        let key = cache.key(&en).unwrap();
+        assert_eq!(key, "GET /api/hello?x=1");
+
+        cache.store(key.clone(), &en.headers, false, &res("max-age=10", "hello"));
+        assert!(matches!(
+            cache.get(&key, &en.headers),
+            Some(CacheLookup::Fresh(r, _)) if r.body.as_deref() == Some("hello")
+        ));
+        assert!(cache.get(&key, &fr.headers).is_none());
+
+        backdate(&cache, 11);
+        assert!(cache.get(&key, &en.headers).is_none());
+
+        cache.store(key.clone(), &en.headers, false, &res("no-store", "hello"));
+        assert!(cache.get(&key, &en.headers).is_none());
+        cache.store(key.clone(), &en.headers, true, &res("max-age=10", "hello"));
+        assert!(cache.get(&key, &en.headers).is_none());
+        cache.store(
+            key.clone(),
+            &en.headers,
+            true,
+            &res("public, max-age=10", "hello"),
+        );
+        assert!(cache.get(&key, &en.headers).is_some());
+
+        let no_cache = parts("/api/hello?x=1", &[("cache-control", "no-cache")]);
+        assert!(cache.get(&key, &no_cache.headers).is_none());
+        let no_store = parts("/api/hello?x=1", &[("cache-control", "no-store")]);
+        assert!(cache.key(&no_store).is_none());
+    }
+
+    #[test]
+    fn response_cache_should_not_share_cookies() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let req = parts("/api/hello", &[]);
+        let key = cache.key(&req).unwrap();
+        let mut set_cookie = res("max-age=10", "hello");
+        set_cookie
+            .headers
+            .insert("Set-Cookie".to_string(), "session=1".to_string());
+        cache.store(key.clone(), &req.headers, false, &set_cookie);
+        assert!(cache.get(&key, &req.headers).is_none());
+
+        let with_cookie = parts("/api/hello", &[("cookie", "session=1")]);
+        cache.store(
+            key.clone(),
+            &with_cookie.headers,
+            false,
+            &res("max-age=10", "hello"),
+        );
+        assert!(cache.get(&key, &with_cookie.headers).is_none());
+        let mut vary_cookie = res("max-age=10", "hello");
+        vary_cookie
+            .headers
+            .insert("Vary".to_string(), "Cookie".to_string());
+        cache.store(key.clone(), &with_cookie.headers, false, &vary_cookie);
+        assert!(cache.get(&key, &with_cookie.headers).is_some());
+        assert!(cache.get(&key, &req.headers).is_none());
+    }
+
+    #[test]
+    fn response_cache_should_serve_stale_while_revalidating() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        let req = parts("/api/hello", &[]);
+        let key = cache.key(&req).unwrap();
+        cache.store(
+            key.clone(),
+            &req.headers,
+            false,
+            &res("s-maxage=10, stale-while-revalidate=30", "hello"),
+        );
+        backdate(&cache, 20);
+        // only the first request refreshes the entry
+        assert!(matches!(
+            cache.get(&key, &req.headers),
+            Some(CacheLookup::Stale(_, _, Some(_)))
+        ));
+        assert!(matches!(
+            cache.get(&key, &req.headers),
+            Some(CacheLookup::Stale(_, _, None))
+        ));
+        backdate(&cache, 30);
+        assert!(cache.get(&key, &req.headers).is_none());
+    }
+}
@@ -1,47 +1,648 @@
-use std::path::Path;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
 
-use anyhow::Result;
-use axum::http::Method;
+use anyhow::{anyhow, Result};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use indexmap::IndexMap;
+use jsonwebtoken::Algorithm;
 use serde::Deserialize;
 
-use crate::ProjectRoutes;
+use crate::{
+    diagnostics::{check_routes, duplicate_routes},
+    ProjectRoutes,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct ProjectConfig {
     pub name: String,
+    #[serde(default)]
     pub routes: ProjectRoutes,
+    /// routes sharing a path prefix and settings, added to `routes`
+    #[serde(default)]
+    pub groups: Vec<RouteGroup>,
+    #[serde(default)]
+    pub limits: ProjectLimits,
+    pub tls: Option<TlsConfig>,
+    /// CORS policy of every route, routes can override it with their own `cors` section
+    pub cors: Option<CorsConfig>,
+    #[serde(default)]
+    pub compression: CompressionConfig,
+    /// limit applied to every route, on top of the routes' own `rate_limit`
+    pub rate_limit: Option<RateLimitConfig>,
+    /// authentication required by every route, routes can override it with their own `auth`
+    pub auth: Option<AuthConfig>,
+    /// directories served next to the js routes
+    #[serde(default, rename = "static")]
+    pub static_files: Vec<StaticConfig>,
+    /// in-memory cache of GET responses, disabled unless configured
+    pub cache: Option<CacheConfig>,
+    #[serde(default)]
+    pub redirects: Vec<RedirectConfig>,
+    /// paths routed as another path, e.g. `/blog/:slug` to `/api/posts/:slug`
+    #[serde(default)]
+    pub rewrites: Vec<RewriteConfig>,
+    /// JSON Schemas referenced by name from the routes' `schema`
+    #[serde(default)]
+    pub schemas: IndexMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub openapi: OpenApiConfig,
+    /// how request paths differing from the route patterns only by their form are handled
+    #[serde(default)]
+    pub paths: PathConfig,
 }
 
-#[derive(Debug, Deserialize)]
+/// Normalizations applied to request paths before they are matched. Each is `strict` to leave
+/// the path as is, `redirect` to the canonical path or `match` to route it as the canonical path.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PathConfig {
+    /// `/api/users/` to `/api/users`
+    pub trailing_slash: PathPolicy,
+    /// `/api//users` to `/api/users`
+    pub duplicate_slashes: PathPolicy,
+    /// `/api/%75sers` to `/api/users`, other escapes are uppercased, e.g. `%2f` to `%2F`
+    pub percent_encoding: PathPolicy,
+    /// `/API/Users` to `/api/users`, params included, escapes are left as is
+    pub case: PathPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PathPolicy {
+    #[default]
+    Strict,
+    Redirect,
+    Match,
+}
+
+/// OpenAPI document generated from the routes, see `dino openapi`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OpenApiConfig {
+    /// title of the API, defaults to the project name
+    pub title: Option<String>,
+    pub version: String,
+    pub description: Option<String>,
+    /// serve the document at `path`
+    pub serve: bool,
+    pub path: String,
+}
+
+/// Certificate served for the tenant's host when it is reached over https.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TlsConfig {
+    /// path to the PEM encoded certificate chain
+    pub cert: PathBuf,
+    /// path to the PEM encoded private key
+    pub key: PathBuf,
+}
+
+/// Resource quotas applied to every request of a tenant. All limits are optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ProjectLimits {
+    /// max number of requests handled at the same time, extra requests get a 503
+    pub max_concurrent_requests: Option<usize>,
+    /// max number of js workers running at the same time, extra requests wait for a free worker
+    pub workers: Option<usize>,
+    /// max heap size of a js worker in bytes
+    pub max_heap_size: Option<usize>,
+    /// max execution time of a handler in milliseconds
+    pub timeout: Option<u64>,
+    /// max number of requests per second, extra requests get a 429
+    pub rate_limit: Option<u32>,
+}
+
+/// Cross-origin requests allowed by browsers. Preflights are answered without calling a handler.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// allowed origins, e.g. `https://example.com`, `*` allows any origin
+    pub origins: Vec<String>,
+    /// allowed methods, defaults to the methods configured on the route
+    #[serde(deserialize_with = "deserialize_methods")]
+    pub methods: Vec<Method>,
+    /// allowed request headers, defaults to the headers asked for by the preflight
+    pub headers: Vec<String>,
+    /// response headers readable by the browser
+    pub expose_headers: Vec<String>,
+    /// allow cookies and authorization headers
+    pub credentials: bool,
+    /// seconds the browser may cache a preflight
+    pub max_age: Option<u64>,
+}
+
+/// Compression of handler responses, negotiated with `Accept-Encoding` (gzip, br or zstd).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    /// responses smaller than this many bytes are sent as is
+    pub min_size: u64,
+    /// content types to compress, matched by prefix, e.g. `text/` or `application/json`
+    pub content_types: Vec<String>,
+}
+
+/// Token bucket limit on the requests sharing a key, e.g. coming from the same client ip.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RateLimitConfig {
+    /// requests allowed in a burst
+    pub requests: u32,
+    /// seconds to refill a whole burst
+    #[serde(default = "default_rate_limit_period")]
+    pub period: u64,
+    #[serde(default)]
+    pub key: RateLimitKey,
+}
+
+/// What requests are counted together by a [`RateLimitConfig`]: `ip`, `header:<name>`, or
+/// `handler:<name>` to call a synchronous js function returning the key. Requests without a key,
+/// e.g. lacking the header, share a single bucket.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum RateLimitKey {
+    #[default]
+    Ip,
+    Header(String),
+    Handler(String),
+}
+
+/// Credentials a request must carry, checked before the handler runs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthConfig {
+    Jwt(JwtConfig),
+    ApiKey(ApiKeyConfig),
+    Basic(BasicAuthConfig),
+    /// no authentication, to exempt a route from the project's `auth`
+    None,
+}
+
+/// Bearer token verified with a shared secret, a PEM public key or a local JWKS file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtConfig {
+    /// shared secret of the HS* algorithms
+    pub secret: Option<String>,
+    /// path to a PEM encoded RSA, EC or Ed25519 public key
+    pub key: Option<PathBuf>,
+    /// path to a JWKS file, keys are picked by `kid`
+    pub jwks: Option<PathBuf>,
+    /// algorithm of `secret` and `key`, and of JWKS keys which don't name one
+    #[serde(default = "default_jwt_algorithm")]
+    pub algorithm: Algorithm,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+}
+
+/// Static keys sent in a header, the key names are passed to the handler as `sub`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiKeyConfig {
+    #[serde(default = "default_api_key_header")]
+    pub header: String,
+    /// key name to key
+    pub keys: IndexMap<String, String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BasicAuthConfig {
+    #[serde(default = "default_basic_auth_realm")]
+    pub realm: String,
+    /// user name to password
+    pub users: IndexMap<String, String>,
+}
+
+/// Files under `dir` served at `prefix`, e.g. `/assets/app.js` from `public/app.js`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaticConfig {
+    pub prefix: String,
+    pub dir: PathBuf,
+    #[serde(default = "default_cache_control")]
+    pub cache_control: String,
+    /// file served for paths which don't exist, e.g. `index.html` of a single page app
+    pub fallback: Option<String>,
+}
+
+/// Redirect from a path pattern to a path or url, params of `from` are substituted in `to`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedirectConfig {
+    pub from: String,
+    pub to: String,
+    /// 308 instead of 307
+    #[serde(default)]
+    pub permanent: bool,
+    /// overrides the status chosen by `permanent`, one of 301, 302, 303, 307 or 308
+    pub status: Option<u16>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RewriteConfig {
+    pub from: String,
+    pub to: String,
+}
+
+/// Responses kept in memory, for as long as the `Cache-Control` set by the handler allows.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// max number of cached urls, the oldest ones are evicted first
+    pub max_entries: usize,
+    /// responses with a larger body in bytes are not cached
+    pub max_body_size: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct ProjectRoute {
-    #[serde(deserialize_with = "deserialize_method")]
-    pub method: Method,
+    #[serde(deserialize_with = "deserialize_route_method")]
+    pub method: RouteMethod,
     pub handler: String,
+    /// what the handler does, shown in the OpenAPI document
+    pub description: Option<String>,
+    /// overrides the project CORS policy for this route
+    pub cors: Option<CorsConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+    /// overrides the project authentication for this route
+    pub auth: Option<AuthConfig>,
+    #[serde(flatten)]
+    pub options: RouteOptions,
+}
+
+/// Routes whose paths start with `prefix`. Their settings default to those of the group, and
+/// groups can be nested.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteGroup {
+    pub prefix: String,
+    pub cors: Option<CorsConfig>,
+    /// limit of each route of the group
+    pub rate_limit: Option<RateLimitConfig>,
+    pub auth: Option<AuthConfig>,
+    #[serde(flatten)]
+    pub options: RouteOptions,
+    /// paths relative to the prefix, e.g. `/users/:id`
+    #[serde(default)]
+    pub routes: ProjectRoutes,
+    #[serde(default)]
+    pub groups: Vec<RouteGroup>,
+}
+
+/// Settings of a route enforced around its handler.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RouteOptions {
+    /// max execution time of the handler in milliseconds, overrides `limits.timeout`
+    pub timeout: Option<u64>,
+    /// max size of the request body in bytes, 2 MiB by default, larger bodies get a 413
+    pub max_body_size: Option<usize>,
+    /// response headers added unless the handler sets them
+    #[serde(deserialize_with = "deserialize_headers")]
+    pub headers: HeaderMap,
+    /// set to false to never cache the responses of the route
+    pub cache: Option<bool>,
+    /// content type required for request bodies, e.g. `application/json`, others get a 415
+    pub content_type: Option<String>,
+    /// constraints on the path params, which are passed to the handler with their type
+    pub params: IndexMap<String, ParamConfig>,
+    /// JSON Schemas the request is validated against before the handler runs
+    pub schema: Option<RequestSchema>,
+}
+
+/// Schemas of the parts of a request. Query and header values are strings, converted to the
+/// type of their property when the schema declares one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RequestSchema {
+    pub query: Option<SchemaRef>,
+    pub params: Option<SchemaRef>,
+    pub headers: Option<SchemaRef>,
+    /// schema of the JSON body
+    pub body: Option<SchemaRef>,
+}
+
+/// A schema given inline, or the name of one in the project's `schemas`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum SchemaRef {
+    Name(String),
+    Inline(serde_json::Value),
+}
+
+/// Constraint on a path param: a type, e.g. `id: int`, or a map with a `type`, a `regex` the
+/// whole value must match and the `enum` of allowed values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ParamConfig {
+    Type(ParamType),
+    Constraint {
+        #[serde(rename = "type", default)]
+        kind: ParamType,
+        regex: Option<String>,
+        #[serde(rename = "enum")]
+        values: Option<Vec<String>>,
+        /// 404 as if the route did not match, or 400
+        #[serde(default = "default_param_status")]
+        status: u16,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Int,
+    Number,
+    Bool,
+    Uuid,
+}
+
+/// Methods of a route: `GET`, a list like `[GET, POST]`, or `ANY` for every method without a
+/// handler of its own. Extension methods, e.g. `PROPFIND`, are accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteMethod {
+    Any,
+    Methods(Vec<Method>),
+}
+
+impl fmt::Display for RouteMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteMethod::Any => write!(f, "ANY"),
+            RouteMethod::Methods(methods) => {
+                let methods = methods.iter().map(Method::as_str).collect::<Vec<_>>();
+                write!(f, "{}", methods.join(","))
+            }
+        }
+    }
 }
 
 impl ProjectConfig {
     pub fn load(filename: impl AsRef<Path>) -> Result<Self> {
         let content = std::fs::read_to_string(filename)?;
-        let config: ProjectConfig = serde_yaml::from_str(&content)?;
-        Ok(config)
+        Self::from_yaml(&content)
+    }
+
+    /// Parse a config, reporting every conflicting or duplicated route with its line.
+    pub fn from_yaml(content: &str) -> Result<Self> {
+        let config: ProjectConfig = serde_yaml::from_str(content)?;
+        let routes = flatten_routes(config.routes.clone(), config.groups.clone());
+        let mut diagnostics = duplicate_routes(content);
+        diagnostics.extend(check_routes(&routes, Some(content)));
+        if diagnostics.is_empty() {
+            return Ok(config);
+        }
+        let diagnostics = diagnostics
+            .iter()
+            .map(|d| format!("  {}", d))
+            .collect::<Vec<_>>();
+        Err(anyhow!("invalid routes:\n{}", diagnostics.join("\n")))
+    }
+}
+
+impl RouteGroup {
+    /// Add the routes of the group and of its nested groups to `routes`, under the prefix and
+    /// with the settings of the group. Routes of a path defined elsewhere too are appended to
+    /// it, so handling a method twice is reported by [`check_routes`].
+    pub fn flatten(mut self, routes: &mut ProjectRoutes) {
+        for (path, group_routes) in std::mem::take(&mut self.routes) {
+            let path = join_path(&self.prefix, &path);
+            let group_routes = group_routes
+                .into_iter()
+                .map(|mut route| {
+                    route.cors = route.cors.or_else(|| self.cors.clone());
+                    route.rate_limit = route.rate_limit.or_else(|| self.rate_limit.clone());
+                    route.auth = route.auth.or_else(|| self.auth.clone());
+                    route.options.inherit(&self.options);
+                    route
+                })
+                .collect::<Vec<_>>();
+            routes.entry(path).or_default().extend(group_routes);
+        }
+        for mut group in std::mem::take(&mut self.groups) {
+            group.prefix = join_path(&self.prefix, &group.prefix);
+            group.cors = group.cors.or_else(|| self.cors.clone());
+            group.rate_limit = group.rate_limit.or_else(|| self.rate_limit.clone());
+            group.auth = group.auth.or_else(|| self.auth.clone());
+            group.options.inherit(&self.options);
+            group.flatten(routes);
+        }
     }
 }
 
-fn deserialize_method<'de, D>(deserializer: D) -> Result<Method, D::Error>
+impl RouteOptions {
+    // take the settings not set from those of a group
+    fn inherit(&mut self, group: &RouteOptions) {
+        self.timeout = self.timeout.or(group.timeout);
+        self.max_body_size = self.max_body_size.or(group.max_body_size);
+        for name in group.headers.keys() {
+            if !self.headers.contains_key(name) {
+                for value in group.headers.get_all(name) {
+                    self.headers.append(name.clone(), value.clone());
+                }
+            }
+        }
+        self.cache = self.cache.or(group.cache);
+        if self.content_type.is_none() {
+            self.content_type.clone_from(&group.content_type);
+        }
+        for (name, param) in &group.params {
+            if !self.params.contains_key(name) {
+                self.params.insert(name.clone(), param.clone());
+            }
+        }
+        self.schema = match (self.schema.take(), &group.schema) {
+            (Some(schema), Some(group)) => Some(RequestSchema {
+                query: schema.query.or_else(|| group.query.clone()),
+                params: schema.params.or_else(|| group.params.clone()),
+                headers: schema.headers.or_else(|| group.headers.clone()),
+                body: schema.body.or_else(|| group.body.clone()),
+            }),
+            (schema, group) => schema.or_else(|| group.clone()),
+        };
+    }
+}
+
+/// `routes` along with the routes of `groups`.
+pub fn flatten_routes(mut routes: ProjectRoutes, groups: Vec<RouteGroup>) -> ProjectRoutes {
+    for group in groups {
+        group.flatten(&mut routes);
+    }
+    routes
+}
+
+// `/api` and `/users` to `/api/users`, `/api` and `/` to `/api`
+pub(crate) fn join_path(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    match path {
+        "/" | "" if !prefix.is_empty() => prefix.to_string(),
+        _ => format!("{}{}", prefix, path),
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size: 1024,
+            content_types: [
+                "text/",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "image/svg+xml",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        }
+    }
+}
+
+impl Default for OpenApiConfig {
+    fn default() -> Self {
+        Self {
+            title: None,
+            version: "0.1.0".to_string(),
+            description: None,
+            serve: false,
+            path: "/openapi.json".to_string(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 1000,
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+impl ProjectLimits {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_millis)
+    }
+}
+
+impl FromStr for RateLimitKey {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "ip" => Ok(RateLimitKey::Ip),
+            Some(("header", name)) if !name.is_empty() => {
+                Ok(RateLimitKey::Header(name.to_lowercase()))
+            }
+            Some(("handler", name)) if !name.is_empty() => Ok(RateLimitKey::Handler(name.into())),
+            _ => Err(anyhow!(
+                "invalid rate limit key {}, expect ip, header:<name> or handler:<name>",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for RateLimitKey {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+fn default_rate_limit_period() -> u64 {
+    1
+}
+
+fn default_param_status() -> u16 {
+    404
+}
+
+fn default_cache_control() -> String {
+    "public, max-age=3600".to_string()
+}
+
+fn default_jwt_algorithm() -> Algorithm {
+    Algorithm::HS256
+}
+
+fn default_api_key_header() -> String {
+    "x-api-key".to_string()
+}
+
+fn default_basic_auth_realm() -> String {
+    "dino".to_string()
+}
+
+fn deserialize_route_method<'de, D>(deserializer: D) -> Result<RouteMethod, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let v = match Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(s) if s.eq_ignore_ascii_case("any") => return Ok(RouteMethod::Any),
+        OneOrMany::One(s) => vec![s],
+        OneOrMany::Many(v) => v,
+    };
+    if v.is_empty() {
+        return Err(serde::de::Error::custom(
+            "a route needs at least one method",
+        ));
+    }
+    v.iter()
+        .map(|s| {
+            parse_method(s).ok_or_else(|| serde::de::Error::custom(format!("invalid method {}", s)))
+        })
+        .collect::<Result<_, _>>()
+        .map(RouteMethod::Methods)
+}
+
+fn deserialize_methods<'de, D>(deserializer: D) -> Result<Vec<Method>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let v: Vec<String> = Deserialize::deserialize(deserializer)?;
+    v.iter()
+        .map(|s| parse_method(s).ok_or_else(|| serde::de::Error::custom("invalid method")))
+        .collect()
+}
+
+fn deserialize_headers<'de, D>(deserializer: D) -> Result<HeaderMap, D::Error>
 where
     D: serde::Deserializer<'de>,
 {
-    let s: String = Deserialize::deserialize(deserializer)?;
+    let v: IndexMap<String, String> = Deserialize::deserialize(deserializer)?;
+    let mut headers = HeaderMap::new();
+    for (name, value) in v {
+        let name = HeaderName::from_bytes(name.as_bytes()).map_err(serde::de::Error::custom)?;
+        let value = HeaderValue::from_str(&value).map_err(serde::de::Error::custom)?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn parse_method(s: &str) -> Option<Method> {
     match s.to_uppercase().as_str() {
-        "GET" => Ok(Method::GET),
-        "POST" => Ok(Method::POST),
-        "PUT" => Ok(Method::PUT),
-        "DELETE" => Ok(Method::DELETE),
-        "PATCH" => Ok(Method::PATCH),
-        "HEAD" => Ok(Method::HEAD),
-        "OPTIONS" => Ok(Method::OPTIONS),
-        "TRACE" => Ok(Method::TRACE),
-        "CONNECT" => Ok(Method::CONNECT),
-        _ => Err(serde::de::Error::custom("invalid method")),
+        "GET" => Some(Method::GET),
+        "POST" => Some(Method::POST),
+        "PUT" => Some(Method::PUT),
+        "DELETE" => Some(Method::DELETE),
+        "PATCH" => Some(Method::PATCH),
+        "HEAD" => Some(Method::HEAD),
+        "OPTIONS" => Some(Method::OPTIONS),
+        "TRACE" => Some(Method::TRACE),
+        "CONNECT" => Some(Method::CONNECT),
+        // ANY is only valid on its own
+        "ANY" => None,
+        s => Method::from_bytes(s.as_bytes()).ok(),
     }
 }
@@ -0,0 +1,188 @@
+use anyhow::Result;
+use axum::http::{header, HeaderMap, HeaderValue, Method};
+
+use crate::CorsConfig;
+
+/// A [`CorsConfig`] with its header values prepared once per swap.
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    // None allows any origin
+    origins: Option<Vec<HeaderValue>>,
+    methods: Vec<Method>,
+    headers: Option<HeaderValue>,
+    expose_headers: Option<HeaderValue>,
+    credentials: bool,
+    max_age: Option<HeaderValue>,
+}
+
+impl CorsPolicy {
+    pub fn try_new(config: &CorsConfig) -> Result<Self> {
+        let origins = if config.origins.iter().any(|o| o == "*") {
+            None
+        } else {
+            Some(
+                config
+                    .origins
+                    .iter()
+                    .map(|o| HeaderValue::from_str(o.trim_end_matches('/')))
+                    .collect::<Result<_, _>>()?,
+            )
+        };
+        Ok(Self {
+            origins,
+            methods: config.methods.clone(),
+            headers: join(&config.headers)?,
+            expose_headers: join(&config.expose_headers)?,
+            credentials: config.credentials,
+            max_age: config.max_age.map(HeaderValue::from),
+        })
+    }
+
+    /// Headers answering a preflight from `origin`. `route_methods` are the methods configured
+    /// on the route, used when the policy does not list any. Nothing but `Vary` is returned
+    /// if the origin or the requested method is not allowed.
+    pub fn preflight(
+        &self,
+        origin: &HeaderValue,
+        request: &HeaderMap,
+        route_methods: &[Method],
+    ) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::VARY, HeaderValue::from_static("origin"));
+        let Some(allow_origin) = self.allow_origin(origin) else {
+            return headers;
+        };
+        let methods = if self.methods.is_empty() {
+            route_methods
+        } else {
+            &self.methods
+        };
+        let requested = request
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| Method::from_bytes(v.as_bytes()).ok());
+        if !requested.is_some_and(|m| methods.contains(&m)) {
+            return headers;
+        }
+
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        let methods = methods
+            .iter()
+            .map(|m| m.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        if let Ok(v) = HeaderValue::from_str(&methods) {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, v);
+        }
+        let allow_headers = self
+            .headers
+            .clone()
+            .or_else(|| request.get(header::ACCESS_CONTROL_REQUEST_HEADERS).cloned());
+        if let Some(v) = allow_headers {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, v);
+        }
+        if self.credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if let Some(v) = self.max_age.clone() {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, v);
+        }
+        headers
+    }
+
+    /// Add the CORS headers of an actual (non preflight) request from `origin` to a response.
+    pub fn decorate(&self, origin: &HeaderValue, headers: &mut HeaderMap) {
+        headers.append(header::VARY, HeaderValue::from_static("origin"));
+        let Some(allow_origin) = self.allow_origin(origin) else {
+            return;
+        };
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if let Some(v) = self.expose_headers.clone() {
+            headers.insert(header::ACCESS_CONTROL_EXPOSE_HEADERS, v);
+        }
+    }
+
+    // browsers reject `*` on credentialed requests, so the origin is echoed back instead
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.origins {
+            None if self.credentials => Some(origin.clone()),
+            None => Some(HeaderValue::from_static("*")),
+            Some(origins) => origins.contains(origin).then(|| origin.clone()),
+        }
+    }
+}
+
+fn join(values: &[String]) -> Result<Option<HeaderValue>> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(HeaderValue::from_str(&values.join(", "))?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cors_preflight_should_check_origin_and_method() -> Result<()> {
+        let config: CorsConfig = serde_yaml::from_str(
+            r#"
+            origins: [https://example.com]
+            headers: [content-type]
+            max_age: 600
+            "#,
+        )?;
+        let policy = CorsPolicy::try_new(&config)?;
+        let origin = HeaderValue::from_static("https://example.com");
+        let mut request = HeaderMap::new();
+        request.insert(
+            header::ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_static("POST"),
+        );
+
+        let headers = policy.preflight(&origin, &request, &[Method::GET, Method::POST]);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+            "content-type"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+
+        let headers = policy.preflight(&origin, &request, &[Method::GET]);
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let other = HeaderValue::from_static("https://evil.com");
+        let headers = policy.preflight(&other, &request, &[Method::POST]);
+        assert!(!headers.contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        Ok(())
+    }
+
+    #[test]
+    fn cors_should_echo_origin_with_credentials() -> Result<()> {
+        let config: CorsConfig = serde_yaml::from_str("origins: ['*']\ncredentials: true")?;
+        let policy = CorsPolicy::try_new(&config)?;
+        let origin = HeaderValue::from_static("https://example.com");
+        let mut headers = HeaderMap::new();
+        policy.decorate(&origin, &mut headers);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+
+        let policy = CorsPolicy::try_new(&CorsConfig {
+            origins: vec!["*".to_string()],
+            ..Default::default()
+        })?;
+        let mut headers = HeaderMap::new();
+        policy.decorate(&origin, &mut headers);
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+use std::{collections::HashMap, fmt};
+
+use matchit::{InsertError, Router};
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_yaml::Value;
+
+use crate::{config::join_path, ProjectRoutes, RouteMethod};
+
+/// A problem in the routes of a project, with the line of the config it comes from when the
+/// source is known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Patterns conflicting with each other and methods handled by more than one route of a path.
+/// Lines are looked up in `source`, the YAML the routes were read from.
+pub fn check_routes(routes: &ProjectRoutes, source: Option<&str>) -> Vec<Diagnostic> {
+    let sources = source
+        .and_then(|source| serde_yaml::from_str::<Value>(source).ok())
+        .map(|value| route_sources(&value))
+        .unwrap_or_default();
+    let mut diagnostics = Vec::new();
+    let mut report = |message: String, path: &str, index: Option<usize>| {
+        let line = source.and_then(|source| locate(source, &sources, path, index));
+        diagnostics.push(Diagnostic { line, message });
+    };
+    let mut router = Router::new();
+    for (path, routes) in routes {
+        if let Err(e) = router.insert(path.as_str(), ()) {
+            let message = match e {
+                InsertError::Conflict { with } => {
+                    format!("route {} conflicts with route {}", path, with)
+                }
+                e => format!("invalid route {}: {}", path, e),
+            };
+            report(message, path, None);
+        }
+        let mut handlers = HashMap::new();
+        for (index, route) in routes.iter().enumerate() {
+            let methods = match &route.method {
+                RouteMethod::Any => vec!["ANY".to_string()],
+                RouteMethod::Methods(methods) => methods.iter().map(|m| m.to_string()).collect(),
+            };
+            for method in methods {
+                let Some(first) = handlers.get(&method) else {
+                    handlers.insert(method, &route.handler);
+                    continue;
+                };
+                let message = if *first == &route.handler {
+                    format!("{} {} is listed twice for {}", method, path, first)
+                } else {
+                    format!(
+                        "{} {} is handled by both {} and {}",
+                        method, path, first, route.handler
+                    )
+                };
+                report(message, path, Some(index));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// A key written twice in a mapping of `source`, e.g. a route path. Deserializing the config
+/// keeps the last one without telling, dropping the routes of the first.
+pub fn duplicate_routes(source: &str) -> Vec<Diagnostic> {
+    let Err(e) = serde_yaml::from_str::<Value>(source) else {
+        return Vec::new();
+    };
+    // prefixed with the path of the mapping, e.g. `routes: duplicate entry with key "/a"`
+    let message = e.to_string();
+    if !message.contains("duplicate entry") {
+        return Vec::new();
+    }
+    // the location is part of the diagnostic already
+    let message = match message.rfind(" at line ") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    };
+    vec![Diagnostic {
+        line: e.location().map(|l| l.line()),
+        message,
+    }]
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// a step from a node of the YAML to one of its children
+#[derive(Debug, Clone)]
+enum Step {
+    Key(String),
+    Index(usize),
+}
+
+// where the routes of a path are written: the path with the prefixes of its groups, the steps
+// to its key, and its routes, in the order they are flattened
+fn route_sources(value: &Value) -> Vec<(String, Vec<Step>, usize)> {
+    let mut sources = Vec::new();
+    collect_routes(value, "", &mut Vec::new(), &mut sources);
+    sources
+}
+
+fn collect_routes(
+    value: &Value,
+    prefix: &str,
+    steps: &mut Vec<Step>,
+    sources: &mut Vec<(String, Vec<Step>, usize)>,
+) {
+    if let Some(routes) = value.get("routes").and_then(Value::as_mapping) {
+        for (key, routes) in routes {
+            let Some(key) = key.as_str() else {
+                continue;
+            };
+            let mut key_steps = steps.clone();
+            key_steps.extend([Step::Key("routes".to_string()), Step::Key(key.to_string())]);
+            let len = routes.as_sequence().map_or(0, Vec::len);
+            sources.push((join_path(prefix, key), key_steps, len));
+        }
+    }
+    let groups = value.get("groups").and_then(Value::as_sequence);
+    for (i, group) in groups.into_iter().flatten().enumerate() {
+        let group_prefix = group
+            .get("prefix")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        steps.extend([Step::Key("groups".to_string()), Step::Index(i)]);
+        collect_routes(group, &join_path(prefix, group_prefix), steps, sources);
+        steps.truncate(steps.len() - 2);
+    }
+}
+
+// line of the `handler` of the `index`th route of `path` once flattened, or of the key of `path`
+fn locate(
+    source: &str,
+    sources: &[(String, Vec<Step>, usize)],
+    path: &str,
+    index: Option<usize>,
+) -> Option<usize> {
+    let mut sources = sources.iter().filter(|(p, ..)| p == path);
+    let Some(mut index) = index else {
+        let (_, steps, _) = sources.next()?;
+        return line_of(source, steps);
+    };
+    for (_, steps, len) in sources {
+        if index < *len {
+            let mut steps = steps.clone();
+            steps.extend([Step::Index(index), Step::Key("handler".to_string())]);
+            return line_of(source, &steps);
+        }
+        index -= len;
+    }
+    None
+}
+
+const FOUND: &str = "dino: key found";
+
+// line of the key at the end of `steps`. The parser attaches the position of the node being read
+// to the errors, so one is raised once the key is reached.
+fn line_of(source: &str, steps: &[Step]) -> Option<usize> {
+    let de = serde_yaml::Deserializer::from_str(source);
+    match Locator(steps).deserialize(de) {
+        Err(e) if e.to_string().contains(FOUND) => e.location().map(|l| l.line()),
+        _ => None,
+    }
+}
+
+struct Locator<'a>(&'a [Step]);
+
+impl<'de> DeserializeSeed<'de> for Locator<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Locator<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a mapping or a sequence")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Some((Step::Index(index), rest)) = self.0.split_first() else {
+            return Ok(());
+        };
+        for _ in 0..*index {
+            seq.next_element::<IgnoredAny>()?;
+        }
+        seq.next_element_seed(Locator(rest))?;
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Some((Step::Key(name), rest)) = self.0.split_first() else {
+            return Ok(());
+        };
+        let last = rest.is_empty();
+        while let Some(matched) = map.next_key_seed(KeyLocator { name, last })? {
+            if matched {
+                return map.next_value_seed(Locator(rest));
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Ok(())
+    }
+}
+
+// whether a key is `name`, raising the error at the key itself when it is the `last` step
+struct KeyLocator<'a> {
+    name: &'a str,
+    last: bool,
+}
+
+impl<'de> DeserializeSeed<'de> for KeyLocator<'_> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for KeyLocator<'_> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a mapping key")
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<bool, E> {
+        match key == self.name {
+            true if self.last => Err(E::custom(FOUND)),
+            matched => Ok(matched),
+        }
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<bool, E> {
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flatten_routes, ProjectConfig};
+    use anyhow::Result;
+
+    #[test]
+    fn check_routes_should_point_at_yaml_lines() -> Result<()> {
+        let source = r#"name: diagnostics
+routes:
+  /api/users/:id:
+    - method: GET
+      handler: getUser
+    - method: [GET, PUT]
+      handler: putUser
+  /api/users/:name:
+  - method: GET
+    handler: byName
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(source)?;
+        let diagnostics = check_routes(&config.routes, Some(source));
+        assert_eq!(
+            diagnostics
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "line 7: GET /api/users/:id is handled by both getUser and putUser",
+                "line 8: route /api/users/:name conflicts with route /api/users/:id",
+            ]
+        );
+
+        let err = ProjectConfig::from_yaml(source).err().unwrap();
+        assert!(err.to_string().contains("line 7: GET /api/users/:id"));
+        Ok(())
+    }
+
+    #[test]
+    fn check_routes_should_point_at_the_group_of_the_route() -> Result<()> {
+        let source = r#"name: groups
+groups:
+  - prefix: /v1
+    routes:
+      /users:
+        - method: GET
+          handler: listV1
+  - prefix: /v2
+    routes:
+      /users:
+        - method: GET
+          handler: listV2
+        - method: GET
+          handler: listV2
+  - prefix: /v2
+    routes:
+      /users:
+        - method: GET
+          handler: other
+"#;
+        let config: ProjectConfig = serde_yaml::from_str(source)?;
+        let routes = flatten_routes(config.routes, config.groups);
+        assert_eq!(
+            check_routes(&routes, Some(source))
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            [
+                "line 14: GET /v2/users is listed twice for listV2",
+                "line 19: GET /v2/users is handled by both listV2 and other",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn duplicate_routes_should_report_the_parser_error() -> Result<()> {
+        let source = r#"name: duplicates
+routes:
+  /api/users:
+    - method: GET
+      handler: a
+  # the same path again
+  /api/users:
+    - method: POST
+      handler: b
+"#;
+        assert_eq!(
+            duplicate_routes(source)
+                .iter()
+                .map(|d| d.to_string())
+                .collect::<Vec<_>>(),
+            ["line 3: routes: duplicate entry with key \"/api/users\""]
+        );
+        assert!(ProjectConfig::from_yaml(source).is_err());
+        Ok(())
+    }
+}
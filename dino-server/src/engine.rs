@@ -1,23 +1,29 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
 use anyhow::Result;
 use axum::{body::Body, response::Response};
 use dino_macros::{FromJs, IntoJs};
 use rquickjs::{Context, Function, Object, Promise, Runtime};
+use tokio_util::sync::CancellationToken;
 use typed_builder::TypedBuilder;
 
-#[allow(unused)]
+use crate::{Claims, Param, ProjectLimits};
+
 pub struct JsWorker {
     rt: Runtime,
     ctx: Context,
+    deadline: Option<Instant>,
 }
 
 fn print(msg: String) {
     println!("{}", msg);
 }
 
-#[derive(Debug, TypedBuilder, IntoJs)]
+#[derive(Debug, Clone, TypedBuilder, IntoJs)]
 pub struct Req {
+    // value of the x-request-id header
+    #[builder(default, setter(into))]
+    pub id: String,
     #[builder(setter(into))]
     pub method: String,
     #[builder(setter(into))]
@@ -25,14 +31,22 @@ pub struct Req {
     #[builder(default)]
     pub query: HashMap<String, String>,
     #[builder(default)]
-    pub params: HashMap<String, String>,
+    pub params: HashMap<String, Param>,
     #[builder(default)]
     pub headers: HashMap<String, String>,
     #[builder(default)]
     pub body: Option<String>,
+    // traceparent/tracestate of the request span. The runtime has no fetch to add them to
+    // outgoing calls, handlers must copy them into the headers of their calls to continue
+    // the trace
+    #[builder(default)]
+    pub trace_headers: HashMap<String, String>,
+    // verified claims when the route requires authentication
+    #[builder(default)]
+    pub claims: Option<Claims>,
 }
 
-#[derive(Debug, FromJs)]
+#[derive(Debug, Clone, FromJs)]
 pub struct Res {
     pub status: u16,
     pub headers: HashMap<String, String>,
@@ -41,7 +55,20 @@ pub struct Res {
 
 impl JsWorker {
     pub fn try_new(module: &str) -> Result<Self> {
+        Self::try_new_with_limits(module, &ProjectLimits::default())
+    }
+
+    /// Create a worker whose heap and execution time are capped by `limits`. The timeout
+    /// counts from the creation of the worker, so a worker is meant to serve a single request.
+    pub fn try_new_with_limits(module: &str, limits: &ProjectLimits) -> Result<Self> {
         let rt = Runtime::new()?;
+        if let Some(size) = limits.max_heap_size {
+            rt.set_memory_limit(size);
+        }
+        let deadline = limits.timeout().map(|timeout| Instant::now() + timeout);
+        if let Some(deadline) = deadline {
+            rt.set_interrupt_handler(Some(Box::new(move || Instant::now() >= deadline)));
+        }
         let ctx = Context::full(&rt)?;
         ctx.with(|ctx| {
             let global = ctx.globals();
@@ -54,7 +81,24 @@ impl JsWorker {
             )?;
             Ok::<_, anyhow::Error>(())
         })?;
-        Ok(Self { rt, ctx })
+        Ok(Self { rt, ctx, deadline })
+    }
+
+    /// Interrupt the running js once `abort` is cancelled, on top of the timeout.
+    pub fn abort_on(&self, abort: CancellationToken) {
+        let deadline = self.deadline;
+        self.rt.set_interrupt_handler(Some(Box::new(move || {
+            abort.is_cancelled() || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+        })));
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    pub fn heap_used(&self) -> u64 {
+        self.rt.memory_usage().memory_used_size as u64
     }
 
     pub fn run(&self, name: &str, req: Req) -> Result<Res> {
@@ -66,6 +110,16 @@ impl JsWorker {
             Ok::<_, anyhow::Error>(v.finish()?)
         })
     }
+
+    /// Call the synchronous function `name`, which computes the rate limit key of a request.
+    pub fn run_key(&self, name: &str, req: Req) -> Result<String> {
+        self.ctx.with(|ctx| {
+            let global = ctx.globals();
+            let handlers: Object = global.get("handlers")?;
+            let func: Function = handlers.get(name)?;
+            Ok::<_, anyhow::Error>(func.call((req,))?)
+        })
+    }
 }
 
 impl From<Res> for Response {
@@ -100,4 +154,51 @@ mod tests {
         let ret = worker.run("hello", req).unwrap();
         assert_eq!(ret.status, 200);
     }
+
+    #[test]
+    fn js_worker_should_time_out() {
+        let code = r#"
+        (function(){async function hello(req){while(true){}}return{hello:hello};})();"#;
+
+        let limits = ProjectLimits {
+            timeout: Some(50),
+            ..Default::default()
+        };
+        let req = Req::builder().method("GET").url("/").build();
+        let worker = JsWorker::try_new_with_limits(code, &limits).unwrap();
+        assert!(worker.run("hello", req).is_err());
+        assert!(worker.is_timed_out());
+    }
+
+    #[test]
+    fn js_worker_should_stop_once_aborted() {
+        let code = r#"
+        (function(){async function hello(req){while(true){}}return{hello:hello};})();"#;
+
+        let req = Req::builder().method("GET").url("/").build();
+        let worker = JsWorker::try_new(code).unwrap();
+        let abort = CancellationToken::new();
+        worker.abort_on(abort.clone());
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            abort.cancel();
+        });
+        assert!(worker.run("hello", req).is_err());
+        assert!(!worker.is_timed_out());
+    }
+
+    #[test]
+    fn js_worker_should_pass_claims() {
+        let code = r#"
+        (function(){async function hello(req){return{status:200,headers:{},body:req.claims.sub};}return{hello:hello};})();"#;
+
+        let req = Req::builder()
+            .method("GET")
+            .url("/")
+            .claims(Some(Claims(serde_json::json!({ "sub": "alice" }))))
+            .build();
+        let worker = JsWorker::try_new(code).unwrap();
+        let ret = worker.run("hello", req).unwrap();
+        assert_eq!(ret.body.as_deref(), Some("alice"));
+    }
 }
@@ -1,9 +1,13 @@
 use axum::{
-    http::{Method, StatusCode},
+    http::{header, HeaderValue, Method, StatusCode},
     response::IntoResponse,
+    Json,
 };
+use serde_json::json;
 use thiserror::Error;
 
+use crate::{allow_header, RateLimitStatus, Violation};
+
 #[derive(Error, Debug)]
 pub enum AppError {
     #[error("Failed to bind to address: {0}")]
@@ -12,8 +16,31 @@ pub enum AppError {
     Anyhow(#[from] anyhow::Error),
     #[error("Path not found: {0}")]
     RoutePathNotFound(String),
-    #[error("Method not found: {0}")]
-    RouteMethodNotAllowed(Method),
+    #[error("Method not allowed: {method}")]
+    RouteMethodNotAllowed {
+        method: Method,
+        /// methods answered on the path, sent in the `Allow` header
+        allowed: Vec<Method>,
+    },
+    #[error("Rate limit exceeded, retry after {}s", .0.retry_after.unwrap_or_default())]
+    RateLimitExceeded(RateLimitStatus),
+    #[error("Unauthorized: {reason}")]
+    Unauthorized { challenge: String, reason: String },
+    #[error("Invalid param {name}: {reason}")]
+    InvalidParam { name: String, reason: String },
+    #[error("Invalid request")]
+    InvalidRequest {
+        status: StatusCode,
+        violations: Vec<Violation>,
+    },
+    #[error("Request body larger than {0} bytes")]
+    PayloadTooLarge(usize),
+    #[error("Unsupported content type, expect {0}")]
+    UnsupportedMediaType(String),
+    #[error("Too many concurrent requests")]
+    ConcurrencyLimited,
+    #[error("Handler execution timed out: {0}")]
+    ExecutionTimeout(String),
 
     #[error("Serde json error: {0}")]
     SerdeJsonError(#[from] serde_json::Error),
@@ -21,13 +48,41 @@ pub enum AppError {
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let code = match self {
+        let code = match &self {
             AppError::HostNotFound(_) => StatusCode::NOT_FOUND,
             AppError::RoutePathNotFound(_) => StatusCode::NOT_FOUND,
-            AppError::RouteMethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::RouteMethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            AppError::RateLimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Unauthorized { .. } => StatusCode::UNAUTHORIZED,
+            AppError::InvalidParam { .. } => StatusCode::BAD_REQUEST,
+            AppError::InvalidRequest { status, .. } => *status,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::ConcurrencyLimited => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::ExecutionTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
             AppError::Anyhow(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::SerdeJsonError(_) => StatusCode::INTERNAL_SERVER_ERROR,
         };
-        (code, self.to_string()).into_response()
+        let mut res = match &self {
+            AppError::InvalidRequest { violations, .. } => {
+                let body = json!({ "error": self.to_string(), "violations": violations });
+                (code, Json(body)).into_response()
+            }
+            _ => (code, self.to_string()).into_response(),
+        };
+        match self {
+            AppError::RateLimitExceeded(status) => status.apply(res.headers_mut()),
+            AppError::RouteMethodNotAllowed { allowed, .. } => {
+                res.headers_mut()
+                    .insert(header::ALLOW, allow_header(&allowed));
+            }
+            AppError::Unauthorized { challenge, .. } => {
+                if let Ok(v) = HeaderValue::from_str(&challenge) {
+                    res.headers_mut().insert(header::WWW_AUTHENTICATE, v);
+                }
+            }
+            _ => {}
+        }
+        res
     }
 }
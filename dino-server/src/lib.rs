@@ -1,37 +1,78 @@
+mod auth;
+mod cache;
 mod config;
+mod cors;
+mod diagnostics;
 mod engine;
 mod error;
+mod listener;
+mod metrics;
 mod middleware;
+mod normalize;
+mod openapi;
+mod params;
+mod quota;
+mod redirect;
+mod registry;
 mod router;
+mod schema;
+mod static_files;
+mod telemetry;
+#[cfg(test)]
+mod test_utils;
+mod tls;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, future::Future, net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Result;
 use axum::{
-    body::Bytes,
-    extract::{Host, Query, State},
-    http::{request::Parts, Response},
+    body::{Body, Bytes, HttpBody},
+    extract::{ConnectInfo, Host, Query, State},
+    http::{header, request::Parts, HeaderValue, Method, Response, StatusCode},
     response::IntoResponse,
     routing::any,
     Router,
 };
 use dashmap::DashMap;
+use http_body_util::LengthLimitError;
 use indexmap::IndexMap;
-use matchit::Match;
-use middleware::ServerTimeLayer;
-use tokio::net::TcpListener;
+use middleware::{
+    compression_layer, current_trace_headers, CorsLayer, RequestIdLayer, RouteInfo,
+    ServerTimeLayer, TraceLayer, REQUEST_ID_HEADER,
+};
+use tokio::{signal, task::JoinSet, time::Instant};
+use tokio_util::sync::CancellationToken;
 
+pub use auth::{Authenticator, Claims};
+use cache::CACHE_STATUS_HEADER;
+pub use cache::{CacheLookup, CachedResponse, ResponseCache};
 pub use config::*;
+pub use cors::CorsPolicy;
+pub use diagnostics::{check_routes, duplicate_routes, Diagnostic};
 pub use engine::*;
 pub use error::AppError;
+pub use listener::{ListenerConfig, ServerConfig};
+pub use metrics::ServerMetrics;
+pub use middleware::{AccessLogConfig, AccessLogLayer, MetricsLayer};
+pub use openapi::openapi_document;
+pub use params::{Param, ParamConstraint};
+pub use quota::*;
+pub use redirect::{Redirected, Redirects};
+pub use registry::*;
 pub use router::*;
-use tracing::info;
+pub use schema::{RequestValidator, Violation};
+pub use static_files::StaticFiles;
+pub use telemetry::Telemetry;
+pub use tls::TenantCertResolver;
+use tracing::{info, info_span, warn, Instrument};
 
 type ProjectRoutes = IndexMap<String, Vec<ProjectRoute>>;
 
 #[derive(Clone)]
 pub struct AppState {
-    router: DashMap<String, SwappableAppRouter>,
+    router: Arc<DashMap<String, SwappableAppRouter>>,
+    // cancelled when the drain timeout is reached, to interrupt the js still running
+    abort: CancellationToken,
 }
 
 #[derive(Clone)]
@@ -40,23 +81,159 @@ pub struct TenentRouter {
     router: SwappableAppRouter,
 }
 
-pub async fn start_server(port: u16, router: Vec<TenentRouter>) -> Result<()> {
-    let addr = format!("0.0.0.0:{}", port);
-    let listener = TcpListener::bind(&addr).await?;
+/// Run the server until SIGINT or SIGTERM is received, then drain in-flight requests.
+pub async fn start_server(
+    config: ServerConfig,
+    router: Vec<TenentRouter>,
+    registry: &dyn TenantRegistry,
+) -> Result<()> {
+    start_server_with_shutdown(config, router, registry, shutdown_signal()).await
+}
+
+/// Like [`start_server`], but stops once `signal` resolves instead of waiting for a unix signal.
+pub async fn start_server_with_shutdown(
+    config: ServerConfig,
+    router: Vec<TenentRouter>,
+    registry: &dyn TenantRegistry,
+    signal: impl Future<Output = ()>,
+) -> Result<()> {
     let map = DashMap::new();
+    // replay tenants deployed before the last restart, routers passed in explicitly take precedence
+    let records = registry.load().unwrap_or_else(|e| {
+        warn!(
+            "Failed to load the tenant registry, starting without it: {:?}",
+            e
+        );
+        Vec::new()
+    });
+    for record in records {
+        match record.to_router() {
+            Ok(r) => {
+                info!("Restored tenant {}", r.host);
+                map.insert(r.host, r.router);
+            }
+            Err(e) => warn!("Failed to restore tenant {}: {:?}", record.host, e),
+        }
+    }
     for r in router {
         map.insert(r.host, r.router);
     }
     let state = AppState::new(map);
+    let metrics = Arc::new(ServerMetrics::try_new(state.clone())?);
+    let mut app = app(state.clone()).layer(MetricsLayer::new(metrics.clone()));
+    if let Some(access_log) = config.access_log.clone() {
+        app = app.layer(AccessLogLayer::new(access_log));
+    }
 
-    info!("Listening on {}", addr);
-    let router = Router::new()
+    // bind every listener before serving any, so a bad address fails the startup
+    let mut listeners = Vec::with_capacity(config.listeners.len());
+    for listener in &config.listeners {
+        listeners.push(listener.bind(&state).await?);
+    }
+    let admin = match config.admin {
+        Some(addr) => Some(ListenerConfig::Tcp(addr).bind(&state).await?),
+        None => None,
+    };
+    let shutdown = CancellationToken::new();
+    let mut tasks = JoinSet::new();
+    for listener in listeners {
+        tasks.spawn(listener.serve(app.clone(), shutdown.clone()));
+    }
+    if let Some(admin) = admin {
+        tasks.spawn(admin.serve(metrics::admin_app(metrics), shutdown.clone()));
+    }
+
+    // a failed listener stops the others the same way as a signal, its error is returned once
+    // they are drained
+    let failure = tokio::select! {
+        Some(ret) = tasks.join_next() => {
+            let failure = ret.map_err(anyhow::Error::from).and_then(|ret| ret).err();
+            if let Some(e) = &failure {
+                warn!("Listener failed, draining connections: {:?}", e);
+            }
+            failure
+        }
+        _ = signal => {
+            info!("Shutdown signal received, draining connections");
+            None
+        }
+    };
+    shutdown.cancel();
+
+    let start = Instant::now();
+    let drained = tokio::time::timeout(config.drain_timeout, async {
+        while let Some(ret) = tasks.join_next().await {
+            match ret {
+                Ok(Err(e)) => warn!("Listener failed while draining: {:?}", e),
+                Err(e) => warn!("Listener task failed while draining: {:?}", e),
+                Ok(Ok(())) => {}
+            }
+        }
+    })
+    .await;
+    let metrics = state.tenant_metrics();
+    let requests: u64 = metrics.iter().map(|(_, m)| m.requests).sum();
+    let in_flight: u64 = metrics.iter().map(|(_, m)| m.in_flight).sum();
+    match drained {
+        Ok(()) => info!(
+            "Server stopped after draining for {:?}, {} requests served",
+            start.elapsed(),
+            requests
+        ),
+        Err(_) => {
+            tasks.abort_all();
+            // aborting the tasks doesn't stop js running on the blocking pool, which would keep
+            // the runtime from shutting down
+            state.abort.cancel();
+            warn!(
+                "Drain timeout of {:?} reached, {} in-flight requests dropped, {} requests served",
+                config.drain_timeout, in_flight, requests
+            );
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = signal::ctrl_c().await {
+            warn!("Failed to listen for ctrl-c: {:?}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {:?}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+pub(crate) fn app(state: AppState) -> Router {
+    Router::new()
         .route("/*path", any(handler))
+        .layer(compression_layer())
+        .layer(CorsLayer::new(state.clone()))
+        .layer(RequestIdLayer)
         .layer(ServerTimeLayer)
-        .with_state(state);
-
-    axum::serve(listener, router.into_make_service()).await?;
-    Ok(())
+        .layer(TraceLayer)
+        .with_state(state)
 }
 
 #[allow(unused)]
@@ -65,25 +242,284 @@ async fn handler(
     parts: Parts,
     Host(mut host): Host,
     Query(query): Query<HashMap<String, String>>,
-    body: Option<Bytes>,
-) -> Result<impl IntoResponse, AppError> {
+    body: Body,
+) -> Response<Body> {
+    host.truncate(host.find(':').unwrap_or(host.len()));
+    // filled in while the request is routed, so errors are recorded under their tenant and
+    // route as well
+    let mut route = None;
+    let mut res = handle(state, parts, host, query, body, &mut route)
+        .await
+        .into_response();
+    if let Some(route) = route {
+        res.extensions_mut().insert(route);
+    }
+    res
+}
+
+async fn handle(
+    state: AppState,
+    parts: Parts,
+    host: String,
+    query: HashMap<String, String>,
+    body: Body,
+    route: &mut Option<RouteInfo>,
+) -> Result<Response<Body>, AppError> {
     // get router from state
-    let router = get_router_by_host(host, state)?;
+    let tenant = get_router_by_host(&host, &state)?;
+    // unknown hosts are left out to keep the cardinality of the metrics bounded
+    *route = Some(RouteInfo {
+        host,
+        pattern: String::new(),
+        handler: String::new(),
+    });
+    let router = tenant.load();
+    let _guard = router.quota.admit(&tenant.metrics)?;
+    if let Some((path, doc)) = &router.openapi {
+        if parts.method == Method::GET && parts.uri.path() == path {
+            let headers = [(header::CONTENT_TYPE, "application/json")];
+            return Ok((headers, doc.clone()).into_response());
+        }
+    }
+    let path = match router.resolve_path(parts.uri.path(), parts.uri.query()) {
+        Ok(path) => path,
+        Err((status, location)) => return redirect(status, location),
+    };
     // match router with parts.path get handler
-    let matched = router.match_it(parts.method.clone(), parts.uri.path())?;
-    let handler = matched.value;
-    let req = assemble_req(&matched, &parts, query, body)?;
-    // convert response into http response and return
+    let matched =
+        info_span!("match_route").in_scope(|| router.match_it(parts.method.clone(), &path));
+    let matched = match matched {
+        // OPTIONS without a handler lists the methods of the path
+        Err(AppError::RouteMethodNotAllowed { method, allowed }) if method == Method::OPTIONS => {
+            let headers = [(header::ALLOW, allow_header(&allowed))];
+            return Ok((StatusCode::NO_CONTENT, headers).into_response());
+        }
+        ret => ret?,
+    };
+    let handler = matched.value.to_string();
+    if let Some(route) = route {
+        route.pattern = matched.pattern.to_string();
+        route.handler = handler.clone();
+    }
+    let limiters = router.rate_limit.iter().chain(matched.rate_limit);
+    let (mut rate_limit, js_limiters) = check_rate_limits(limiters, &parts, &tenant.metrics)?;
+    let claims = match matched.auth {
+        Some(auth) => auth.authenticate(&parts.headers)?,
+        None => None,
+    };
+    if let Some(files) = matched.static_files {
+        let path = matched.params.get(STATIC_PATH_PARAM).unwrap_or_default();
+        let mut res = files.serve(&parts, path).await;
+        if let Some(status) = rate_limit {
+            status.apply(res.headers_mut());
+        }
+        res.extensions_mut().insert(router.compression.clone());
+        return Ok(res);
+    }
+    let options = matched.options.cloned().unwrap_or_default();
+    let body = read_body(&options, &parts, body).await?;
+    if let Some(validator) = matched.validator {
+        validator.validate(&parts.headers, &query, &matched.typed_params, Some(&body))?;
+    }
+    let authenticated = claims.is_some() || parts.headers.contains_key(header::AUTHORIZATION);
+    let req = assemble_req(&matched, &parts, query, Some(body), claims)?;
+    // limits keyed by a js function need a worker, so those routes are never cached
+    let cache = router
+        .cache
+        .clone()
+        .filter(|_| js_limiters.is_empty() && options.cache != Some(false));
+    let cache_key = cache.as_ref().and_then(|c| c.key(&parts));
+    let lookup = match (&cache, &cache_key) {
+        (Some(cache), Some(key)) => cache.get(key, &parts.headers),
+        _ => None,
+    };
+    let compression = router.compression.clone();
+    let mut res = match lookup {
+        Some(CacheLookup::Fresh(res, age)) => cached_response(res, age, "hit"),
+        Some(CacheLookup::Stale(res, age, entry)) => {
+            if let (Some(entry), Some(cache), Some(key)) = (entry, cache, cache_key) {
+                let headers = parts.headers.clone();
+                let run = run_handler(
+                    state.abort.clone(),
+                    router,
+                    tenant.metrics.clone(),
+                    handler,
+                    req,
+                    options.timeout,
+                    vec![],
+                    None,
+                );
+                tokio::spawn(
+                    async move {
+                        match run.await {
+                            Ok((res, _)) => cache.store(key, &headers, authenticated, &res),
+                            Err(e) => {
+                                warn!("Failed to revalidate cached response: {:?}", e);
+                                entry.revalidation_failed();
+                            }
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+            cached_response(res, age, "stale")
+        }
+        None => {
+            let run = run_handler(
+                state.abort.clone(),
+                router,
+                tenant.metrics.clone(),
+                handler,
+                req,
+                options.timeout,
+                js_limiters,
+                rate_limit,
+            );
+            let (res, status) = run.await?;
+            rate_limit = status;
+            if let (Some(cache), Some(key)) = (&cache, cache_key) {
+                cache.store(key, &parts.headers, authenticated, &res);
+            }
+            let mut res = Response::from(res);
+            if cache.is_some() {
+                res.headers_mut()
+                    .insert(CACHE_STATUS_HEADER, HeaderValue::from_static("miss"));
+            }
+            res
+        }
+    };
+    if let Some(status) = rate_limit {
+        status.apply(res.headers_mut());
+    }
+    for (name, value) in &options.headers {
+        if !res.headers().contains_key(name) {
+            res.headers_mut().insert(name, value.clone());
+        }
+    }
+    if parts.method == Method::HEAD {
+        strip_body(&mut res);
+    }
+    res.extensions_mut().insert(compression);
+    Ok(res)
+}
+
+/// Run a js handler on a new worker, once one is available under the tenant's quota. The
+/// limits keyed by a js function are checked first, tightening `rate_limit`. `timeout`
+/// overrides the one of the project. The handler is interrupted once `abort` is cancelled.
+#[allow(clippy::too_many_arguments)]
+async fn run_handler(
+    abort: CancellationToken,
+    router: AppRouter,
+    metrics: Arc<TenantMetrics>,
+    handler: String,
+    req: Req,
+    timeout: Option<u64>,
+    js_limiters: Vec<Arc<KeyedRateLimiter>>,
+    mut rate_limit: Option<RateLimitStatus>,
+) -> Result<(Res, Option<RateLimitStatus>), AppError> {
     // TODO: build a worker pool, and send req via mpsc channel and get res from oneshot channel
     // but if code change, we need to restart the worker
-    let worker = JsWorker::try_new(&router.code)?;
-    let res = worker.run(handler, req)?;
-    Ok(Response::from(res))
+    let _permit = router
+        .quota
+        .acquire_worker()
+        .instrument(info_span!("acquire_worker"))
+        .await;
+    let _busy = metrics.worker_busy();
+    let span = info_span!("run_js", handler);
+    tokio::task::spawn_blocking(move || {
+        let _enter = span.enter();
+        let mut limits = router.quota.limits().clone();
+        limits.timeout = timeout.or(limits.timeout);
+        let worker = JsWorker::try_new_with_limits(&router.code, &limits)?;
+        worker.abort_on(abort);
+        for limiter in &js_limiters {
+            if let RateLimitKey::Handler(name) = limiter.key() {
+                let status = limiter.check(&worker.run_key(name, req.clone())?, &metrics)?;
+                rate_limit = Some(RateLimitStatus::tightest(rate_limit, status));
+            }
+        }
+        let ret = worker.run(&handler, req);
+        metrics.record_heap_used(worker.heap_used());
+        ret.map(|res| (res, rate_limit)).map_err(|e| {
+            if worker.is_timed_out() {
+                metrics.record_timeout();
+                AppError::ExecutionTimeout(handler)
+            } else {
+                e.into()
+            }
+        })
+    })
+    .await
+    .map_err(anyhow::Error::from)?
+}
+
+/// Body size allowed for routes without a `max_body_size`, the default limit of axum.
+const DEFAULT_MAX_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+// buffer the body up to the size allowed by the route, so larger bodies are never read in full,
+// and check its content type
+async fn read_body(options: &RouteOptions, parts: &Parts, body: Body) -> Result<Bytes, AppError> {
+    let max = options.max_body_size.unwrap_or(DEFAULT_MAX_BODY_SIZE);
+    let body = axum::body::to_bytes(body, max).await.map_err(|e| {
+        let e = e.into_inner();
+        match e.is::<LengthLimitError>() {
+            true => AppError::PayloadTooLarge(max),
+            false => anyhow::anyhow!(e).into(),
+        }
+    })?;
+    let Some(expected) = &options.content_type else {
+        return Ok(body);
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .unwrap_or_default();
+    if !body.is_empty() && !content_type.trim().eq_ignore_ascii_case(expected) {
+        return Err(AppError::UnsupportedMediaType(expected.clone()));
+    }
+    Ok(body)
+}
+
+fn redirect(status: StatusCode, location: String) -> Result<Response<Body>, AppError> {
+    let location = HeaderValue::try_from(location).map_err(anyhow::Error::from)?;
+    Ok((status, [(header::LOCATION, location)]).into_response())
+}
+
+// only the headers of a HEAD response are sent, with the length of the body it would have
+fn strip_body(res: &mut Response<Body>) {
+    if let Some(len) = res.body().size_hint().exact() {
+        res.headers_mut()
+            .entry(header::CONTENT_LENGTH)
+            .or_insert(HeaderValue::from(len));
+    }
+    *res.body_mut() = Body::empty();
+}
+
+// a response served from the cache, with its age in seconds
+fn cached_response(res: Res, age: Duration, cache_status: &'static str) -> Response<Body> {
+    let mut res = Response::from(res);
+    let headers = res.headers_mut();
+    headers.insert(header::AGE, HeaderValue::from(age.as_secs()));
+    headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static(cache_status));
+    res
 }
 
 impl AppState {
     pub fn new(router: DashMap<String, SwappableAppRouter>) -> Self {
-        Self { router }
+        Self {
+            router: Arc::new(router),
+            abort: CancellationToken::new(),
+        }
+    }
+
+    /// Metrics of every tenant, keyed by host.
+    pub fn tenant_metrics(&self) -> Vec<(String, TenantMetricsSnapshot)> {
+        self.router
+            .iter()
+            .map(|r| (r.key().clone(), r.value().metrics.snapshot()))
+            .collect()
     }
 }
 
@@ -96,27 +532,66 @@ impl TenentRouter {
     }
 }
 
-fn get_router_by_host(mut host: String, state: AppState) -> Result<AppRouter, AppError> {
-    host.truncate(host.find(':').unwrap_or(host.len()));
-
+fn get_router_by_host(host: &str, state: &AppState) -> Result<SwappableAppRouter, AppError> {
     Ok(state
         .router
-        .get(&host)
+        .get(host)
         .ok_or(AppError::HostNotFound(host.to_string()))?
-        .load())
+        .clone())
+}
+
+/// Check the rate limits keyed by the request itself, and return the ones keyed by a js
+/// function, to be checked once a worker is running.
+fn check_rate_limits<'a>(
+    limiters: impl Iterator<Item = &'a Arc<KeyedRateLimiter>>,
+    parts: &Parts,
+    metrics: &TenantMetrics,
+) -> Result<(Option<RateLimitStatus>, Vec<Arc<KeyedRateLimiter>>), AppError> {
+    let mut status = None;
+    let mut js_limiters = Vec::new();
+    for limiter in limiters {
+        let key = match limiter.key() {
+            RateLimitKey::Ip => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|info| info.0.ip().to_string())
+                // unix sockets have no peer address, expect a proxy in front of them
+                .or_else(|| {
+                    parts
+                        .headers
+                        .get("x-forwarded-for")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.split(',').next())
+                        .map(|v| v.trim().to_string())
+                })
+                .unwrap_or_default(),
+            RateLimitKey::Header(name) => parts
+                .headers
+                .get(name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string(),
+            RateLimitKey::Handler(_) => {
+                js_limiters.push(limiter.clone());
+                continue;
+            }
+        };
+        status = Some(RateLimitStatus::tightest(
+            status,
+            limiter.check(&key, metrics)?,
+        ));
+    }
+    Ok((status, js_limiters))
 }
 
 fn assemble_req(
-    matched: &Match<&str>,
+    matched: &RouteMatch,
     parts: &Parts,
     query: HashMap<String, String>,
     body: Option<Bytes>,
+    claims: Option<Claims>,
 ) -> Result<Req, AppError> {
-    let params = matched
-        .params
-        .iter()
-        .map(|(k, v)| (k.to_string(), v.to_string()))
-        .collect::<HashMap<_, _>>();
+    let params = matched.typed_params.clone();
     // convert request data into Req and call handler with a js runtime
     let headers = parts
         .headers
@@ -124,14 +599,370 @@ fn assemble_req(
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap().to_string()))
         .collect::<HashMap<_, _>>();
     let body = body.and_then(|v| String::from_utf8(v.into()).ok());
+    let id = parts
+        .headers
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
 
     let req = Req::builder()
+        .id(id)
         .method(parts.method.to_string())
         .url(parts.uri.to_string())
         .headers(headers)
         .query(query)
         .params(params)
+        .trace_headers(current_trace_headers())
+        .claims(claims)
         .body(body)
         .build();
     Ok(req)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{test_app, test_state, HELLO_CODE};
+    use axum::{body::Body, http::Request};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        sync::oneshot,
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn rate_limit_should_apply_per_key() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: rate-limit
+            rate_limit:
+              requests: 1
+              period: 60
+              key: header:x-api-key
+            routes:
+              /api/:name:
+                - method: GET
+                  handler: hello
+                  rate_limit:
+                    requests: 5
+                    key: handler:userKey
+            "#,
+        )?;
+        let code = r#"
+        (function(){
+            async function hello(req){return{status:200,headers:{},body:"hello"};}
+            function userKey(req){return req.params.name;}
+            return{hello:hello,userKey:userKey};
+        })();"#;
+        let app = test_app(code, config)?;
+        let get = |key: &str| {
+            Request::get("/api/alice")
+                .header("host", "localhost")
+                .header("x-api-key", key)
+                .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(get("a")?).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["ratelimit-limit"], "1");
+        assert_eq!(res.headers()["ratelimit-remaining"], "0");
+
+        let res = app.clone().oneshot(get("a")?).await?;
+        assert_eq!(res.status(), 429);
+        assert_eq!(res.headers()["retry-after"], "60");
+
+        // another api key, the js computed key has requests left
+        let res = app.oneshot(get("b")?).await?;
+        assert_eq!(res.status(), 200);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn response_cache_should_serve_hits_until_swap() -> Result<()> {
+        let config = r#"
+            name: cache
+            cache:
+              max_entries: 10
+            routes:
+              /api/:name:
+                - method: GET
+                  handler: hello
+            "#;
+        let code = r#"
+        (function(){async function hello(req){return{status:200,headers:{"cache-control":"max-age=60"},body:String(Math.random())};}return{hello:hello};})();"#;
+        let state = test_state(code, serde_yaml::from_str(config)?)?;
+        let router = state.router.get("localhost").unwrap().clone();
+        let app = app(state);
+        let get = |cache_control: &str| {
+            Request::get("/api/alice")
+                .header("host", "localhost")
+                .header("cache-control", cache_control)
+                .body(Body::empty())
+        };
+        let send = |req: Request<Body>| {
+            let app = app.clone();
+            async move {
+                let res = app.oneshot(req).await?;
+                let status = res.headers()["x-cache"].to_str()?.to_string();
+                let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+                Ok::<_, anyhow::Error>((status, body))
+            }
+        };
+
+        let (status, first) = send(get("")?).await?;
+        assert_eq!(status, "miss");
+        let (status, body) = send(get("")?).await?;
+        assert_eq!((status.as_str(), &body), ("hit", &first));
+        let (status, body) = send(get("no-cache")?).await?;
+        assert_eq!(status, "miss");
+        assert_ne!(body, first);
+
+        router.swap(code, serde_yaml::from_str(config)?)?;
+        let (status, _) = send(get("")?).await?;
+        assert_eq!(status, "miss");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn head_and_options_should_be_answered_automatically() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+        let app = test_app(HELLO_CODE, config)?;
+        let req = |method: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/api/hello/1")
+                .header("host", "localhost")
+                .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(req("HEAD")?).await?;
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()["content-length"], "5");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert!(body.is_empty());
+
+        let res = app.clone().oneshot(req("OPTIONS")?).await?;
+        assert_eq!(res.status(), 204);
+        assert_eq!(res.headers()["allow"], "GET, POST, HEAD, OPTIONS");
+
+        let res = app.oneshot(req("DELETE")?).await?;
+        assert_eq!(res.status(), 405);
+        assert_eq!(res.headers()["allow"], "GET, POST, HEAD, OPTIONS");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn route_options_should_be_enforced() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: options
+            limits:
+              timeout: 5000
+            routes:
+              /api/items:
+                - method: POST
+                  handler: create
+                  max_body_size: 16
+                  content_type: application/json
+                  headers:
+                    x-frame-options: DENY
+                    content-type: text/plain
+              /api/slow:
+                - method: GET
+                  handler: slow
+                  timeout: 50
+            "#,
+        )?;
+        let code = r#"
+        (function(){
+            async function create(req){return{status:201,headers:{"content-type":"application/json"},body:req.body};}
+            async function slow(req){while(true){}}
+            return{create:create,slow:slow};
+        })();"#;
+        let app = test_app(code, config)?;
+        let post = |content_type: &str, body: &'static str| {
+            Request::post("/api/items")
+                .header("host", "localhost")
+                .header("content-type", content_type)
+                .body(Body::from(body))
+        };
+
+        let res = app.clone().oneshot(post("application/json", "{}")?).await?;
+        assert_eq!(res.status(), 201);
+        assert_eq!(res.headers()["x-frame-options"], "DENY");
+        assert_eq!(res.headers()["content-type"], "application/json");
+        let res = app.clone().oneshot(post("text/plain", "{}")?).await?;
+        assert_eq!(res.status(), 415);
+        let res = app
+            .clone()
+            .oneshot(post("application/json", "[1,2,3,4,5,6,7,8,9]")?)
+            .await?;
+        assert_eq!(res.status(), 413);
+
+        let req = Request::get("/api/slow")
+            .header("host", "localhost")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.status(), 504);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn body_limit_should_apply_to_large_bodies() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: body-limit
+            routes:
+              /api/default:
+                - method: POST
+                  handler: size
+              /api/upload:
+                - method: POST
+                  handler: size
+                  max_body_size: 10000000
+            "#,
+        )?;
+        let code = r#"
+        (function(){async function size(req){return{status:200,headers:{},body:String(req.body.length)};}return{size:size};})();"#;
+        let app = test_app(code, config)?;
+        let post = |path: &str| {
+            Request::post(path)
+                .header("host", "localhost")
+                .body(Body::from("a".repeat(3_000_000)))
+        };
+
+        // larger than the default limit, which used to hand the handler no body at all
+        let res = app.clone().oneshot(post("/api/default")?).await?;
+        assert_eq!(res.status(), 413);
+        let res = app.oneshot(post("/api/upload")?).await?;
+        assert_eq!(res.status(), 200);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body, "3000000");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn redirects_and_rewrites_should_apply_before_routing() -> Result<()> {
+        let config = format!(
+            "{}\nredirects:\n  - from: /old/:id\n    to: /api/hello/:id\nrewrites:\n  - from: /hello/:id\n    to: /api/hello/:id\n",
+            include_str!("../fixtures/config.yml")
+        );
+        let code = r#"
+        (function(){async function hello(req){return{status:200,headers:{},body:req.params.id};}return{hello:hello};})();"#;
+        let app = test_app(code, serde_yaml::from_str(&config)?)?;
+        let get = |path: &str| {
+            Request::get(path)
+                .header("host", "localhost")
+                .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(get("/old/1?a=b")?).await?;
+        assert_eq!(res.status(), 307);
+        assert_eq!(res.headers()["location"], "/api/hello/1?a=b");
+        let res = app.oneshot(get("/hello/2")?).await?;
+        assert_eq!(res.status(), 200);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body, "2");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paths_should_be_normalized_by_policy() -> Result<()> {
+        let config = format!(
+            "{}\npaths:\n  trailing_slash: match\n  duplicate_slashes: redirect\n  percent_encoding: redirect\n",
+            include_str!("../fixtures/config.yml")
+        );
+        let code = r#"
+        (function(){async function hello(req){return{status:200,headers:{},body:req.params.id};}return{hello:hello};})();"#;
+        let app = test_app(code, serde_yaml::from_str(&config)?)?;
+        let get = |path: &str| {
+            Request::get(path)
+                .header("host", "localhost")
+                .body(Body::empty())
+        };
+
+        let res = app.clone().oneshot(get("/api/hello/1/")?).await?;
+        assert_eq!(res.status(), 200);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body, "1");
+        let res = app.clone().oneshot(get("/api//hello/1?a=b")?).await?;
+        assert_eq!(res.status(), 308);
+        assert_eq!(res.headers()["location"], "/api/hello/1?a=b");
+        // the path matches a route as sent, but is normalized first
+        let res = app.oneshot(get("/api/hello/%31")?).await?;
+        assert_eq!(res.status(), 308);
+        assert_eq!(res.headers()["location"], "/api/hello/1");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn path_redirects_should_stay_on_the_host() -> Result<()> {
+        let config = format!(
+            "{}\npaths:\n  trailing_slash: redirect\n",
+            include_str!("../fixtures/config.yml")
+        );
+        let app = test_app(HELLO_CODE, serde_yaml::from_str(&config)?)?;
+        for path in ["//evil.com/", "/\\evil.com/", "///evil.com//"] {
+            let req = Request::get(path)
+                .header("host", "localhost")
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), 308);
+            assert!(res.headers()["location"].to_str()?.starts_with("/evil.com"));
+        }
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn server_should_drain_in_flight_requests_on_shutdown() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("dino.sock");
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+        let code = r#"
+        (function(){async function hello(req){let s=Date.now();while(Date.now()-s<200){}return{status:200,headers:{},body:"hello"};}return{hello:hello};})();"#;
+        let router = vec![TenentRouter::new(
+            "localhost",
+            SwappableAppRouter::try_new(code, config)?,
+        )];
+        let server_config = ServerConfig::new(vec![ListenerConfig::Unix(path.clone())]);
+        let (tx, rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            let registry = FileRegistry::new(dir.path().join("registry.json"));
+            start_server_with_shutdown(server_config, router, &registry, async {
+                rx.await.ok();
+            })
+            .await
+        });
+
+        let mut stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        stream
+            .write_all(b"GET /api/hello/1 HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        tx.send(()).unwrap();
+
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).await?;
+        assert!(buf.starts_with("HTTP/1.1 200 OK"));
+        assert!(buf.ends_with("hello"));
+        server.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_should_start_with_a_corrupt_registry() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("registry.json");
+        std::fs::write(&path, "{ not json")?;
+        let registry = FileRegistry::new(path);
+        let server_config = ServerConfig::new(vec![ListenerConfig::Tcp("127.0.0.1:0".parse()?)]);
+        start_server_with_shutdown(server_config, vec![], &registry, async {}).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,338 @@
+use std::{fmt, future::Future, io, net::SocketAddr, str::FromStr, time::Duration};
+
+#[cfg(unix)]
+use std::{os::unix::fs::FileTypeExt, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use axum::{extract::ConnectInfo, http::Request, Router};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::conn::auto,
+    service::TowerToHyperService,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tower::ServiceExt;
+use tracing::{info, warn};
+
+use crate::{tls, AccessLogConfig, AppState};
+
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+// pause after an accept error that is not about a single connection, e.g. too many open files
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Where and how the server accepts connections.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listeners: Vec<ListenerConfig>,
+    /// how long to wait for in-flight requests on shutdown before dropping them
+    pub drain_timeout: Duration,
+    /// per request access log, disabled when `None`
+    pub access_log: Option<AccessLogConfig>,
+    /// address of the admin listener serving `/metrics`, disabled when `None`
+    pub admin: Option<SocketAddr>,
+}
+
+/// A single listener, parsed from `127.0.0.1:3000`, `http://[::1]:3000`,
+/// `https://0.0.0.0:3443` or `unix:/tmp/dino.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenerConfig {
+    Tcp(SocketAddr),
+    Tls(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+pub(crate) enum BoundListener {
+    Tcp(TcpListener),
+    Tls(TcpListener, TlsAcceptor),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl ServerConfig {
+    pub fn new(listeners: Vec<ListenerConfig>) -> Self {
+        Self {
+            listeners,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            access_log: Some(AccessLogConfig::default()),
+            admin: None,
+        }
+    }
+
+    /// Plain http on all interfaces, and https as well if `tls_port` is given.
+    pub fn from_ports(port: u16, tls_port: Option<u16>) -> Self {
+        let mut listeners = vec![ListenerConfig::Tcp(SocketAddr::from(([0, 0, 0, 0], port)))];
+        if let Some(tls_port) = tls_port {
+            listeners.push(ListenerConfig::Tls(SocketAddr::from((
+                [0, 0, 0, 0],
+                tls_port,
+            ))));
+        }
+        Self::new(listeners)
+    }
+}
+
+impl ListenerConfig {
+    pub(crate) async fn bind(&self, state: &AppState) -> Result<BoundListener> {
+        let listener = match self {
+            ListenerConfig::Tcp(addr) => BoundListener::Tcp(TcpListener::bind(addr).await?),
+            ListenerConfig::Tls(addr) => BoundListener::Tls(
+                TcpListener::bind(addr).await?,
+                tls::tls_acceptor(state.clone())?,
+            ),
+            #[cfg(unix)]
+            ListenerConfig::Unix(path) => {
+                // a socket file left behind by a previous run would make bind fail, any other
+                // file is left alone
+                match std::fs::symlink_metadata(path) {
+                    Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+                    Ok(_) => return Err(anyhow!("{} exists and is not a socket", path.display())),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+                BoundListener::Unix(tokio::net::UnixListener::bind(path)?)
+            }
+        };
+        info!("Listening on {}", self);
+        Ok(listener)
+    }
+}
+
+impl BoundListener {
+    /// Accept connections until `shutdown` is cancelled, then wait for the open ones to finish.
+    pub(crate) async fn serve(self, app: Router, shutdown: CancellationToken) -> Result<()> {
+        match self {
+            BoundListener::Tcp(listener) => {
+                axum::serve(
+                    listener,
+                    app.into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await?;
+                Ok(())
+            }
+            BoundListener::Tls(listener, acceptor) => {
+                tls::serve_tls(listener, acceptor, app, shutdown).await
+            }
+            #[cfg(unix)]
+            BoundListener::Unix(listener) => {
+                let tracker = TaskTracker::new();
+                loop {
+                    let stream = tokio::select! {
+                        (stream, _) = accept(|| listener.accept()) => stream,
+                        _ = shutdown.cancelled() => break,
+                    };
+                    tracker.spawn(serve_connection(
+                        stream,
+                        None,
+                        app.clone(),
+                        shutdown.clone(),
+                    ));
+                }
+                tracker.close();
+                tracker.wait().await;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Serve http/1 or http/2 on an already accepted connection, closing it gracefully once
+/// `shutdown` is cancelled. The `peer` address is made available as [`ConnectInfo`].
+pub(crate) async fn serve_connection<I>(
+    io: I,
+    peer: Option<SocketAddr>,
+    app: Router,
+    shutdown: CancellationToken,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let service = TowerToHyperService::new(app.map_request(insert_peer(peer)));
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let conn = builder.serve_connection_with_upgrades(TokioIo::new(io), service);
+    tokio::pin!(conn);
+    let ret = tokio::select! {
+        ret = conn.as_mut() => ret,
+        _ = shutdown.cancelled() => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+        }
+    };
+    if let Err(e) = ret {
+        warn!("Failed to serve connection: {:?}", e);
+    }
+}
+
+/// Wait for the next connection. Accept errors are logged and retried, as `axum::serve` does,
+/// instead of stopping the listener.
+pub(crate) async fn accept<T, F>(mut accept: impl FnMut() -> F) -> T
+where
+    F: Future<Output = io::Result<T>>,
+{
+    loop {
+        match accept().await {
+            Ok(ret) => return ret,
+            // the connection went away before it was accepted
+            Err(e) if is_connection_error(&e) => {}
+            Err(e) => {
+                warn!("Failed to accept connection: {:?}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+            }
+        }
+    }
+}
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+fn insert_peer<B>(peer: Option<SocketAddr>) -> impl Fn(Request<B>) -> Request<B> + Clone {
+    move |mut req| {
+        if let Some(peer) = peer {
+            req.extensions_mut().insert(ConnectInfo(peer));
+        }
+        req
+    }
+}
+
+impl FromStr for ListenerConfig {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return Ok(ListenerConfig::Unix(PathBuf::from(
+                path.strip_prefix("//").unwrap_or(path),
+            )));
+            #[cfg(not(unix))]
+            return Err(anyhow!("unix sockets are not supported: {}", path));
+        }
+        if let Some(addr) = s.strip_prefix("https://") {
+            return Ok(ListenerConfig::Tls(parse_addr(addr)?));
+        }
+        let addr = s
+            .strip_prefix("http://")
+            .or_else(|| s.strip_prefix("tcp://"))
+            .unwrap_or(s);
+        Ok(ListenerConfig::Tcp(parse_addr(addr)?))
+    }
+}
+
+impl fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenerConfig::Tcp(addr) => write!(f, "http://{}", addr),
+            ListenerConfig::Tls(addr) => write!(f, "https://{}", addr),
+            #[cfg(unix)]
+            ListenerConfig::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+fn parse_addr(s: &str) -> Result<SocketAddr> {
+    s.parse()
+        .map_err(|e| anyhow!("invalid listen address {}: {}", s, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listener_config_should_parse() -> Result<()> {
+        assert_eq!(
+            "127.0.0.1:3000".parse::<ListenerConfig>()?,
+            ListenerConfig::Tcp("127.0.0.1:3000".parse()?)
+        );
+        assert_eq!(
+            "http://[::1]:3000".parse::<ListenerConfig>()?,
+            ListenerConfig::Tcp("[::1]:3000".parse()?)
+        );
+        assert_eq!(
+            "https://0.0.0.0:3443".parse::<ListenerConfig>()?,
+            ListenerConfig::Tls("0.0.0.0:3443".parse()?)
+        );
+        #[cfg(unix)]
+        {
+            assert_eq!(
+                "unix:/tmp/dino.sock".parse::<ListenerConfig>()?,
+                ListenerConfig::Unix("/tmp/dino.sock".into())
+            );
+            assert_eq!(
+                "unix:///tmp/dino.sock".parse::<ListenerConfig>()?,
+                ListenerConfig::Unix("/tmp/dino.sock".into())
+            );
+        }
+        assert!("localhost".parse::<ListenerConfig>().is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn accept_should_retry_errors() {
+        let mut errors = vec![
+            io::Error::from(io::ErrorKind::ConnectionAborted),
+            io::Error::other("too many open files"),
+        ];
+        let ret = accept(|| {
+            let ret = errors.pop().map_or(Ok(42), Err);
+            async move { ret }
+        })
+        .await;
+        assert_eq!(ret, 42);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_listener_should_serve() -> Result<()> {
+        use crate::{
+            test_utils::{test_state, HELLO_CODE},
+            ProjectConfig,
+        };
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("dino.sock");
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+        let state = test_state(HELLO_CODE, config)?;
+
+        let listener = ListenerConfig::Unix(path.clone()).bind(&state).await?;
+        tokio::spawn(listener.serve(crate::app(state), CancellationToken::new()));
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await?;
+        stream
+            .write_all(b"GET /api/hello/1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await?;
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).await?;
+        assert!(buf.starts_with("HTTP/1.1 200 OK"));
+        assert!(buf.ends_with("hello"));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_listener_should_not_remove_regular_files() -> Result<()> {
+        use dashmap::DashMap;
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.yml");
+        std::fs::write(&path, "name: keep")?;
+        let state = AppState::new(DashMap::new());
+        assert!(ListenerConfig::Unix(path.clone())
+            .bind(&state)
+            .await
+            .is_err());
+        assert_eq!(std::fs::read_to_string(&path)?, "name: keep");
+        Ok(())
+    }
+}
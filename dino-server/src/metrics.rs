@@ -0,0 +1,239 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use prometheus::{
+    core::{Collector, Desc},
+    proto::{Counter, Gauge, LabelPair, Metric, MetricFamily, MetricType},
+    HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{AppError, AppState, TenantMetricsSnapshot};
+
+const REQUEST_LABELS: [&str; 4] = ["host", "route", "handler", "status"];
+
+/// Prometheus metrics of the server, rendered by the admin `/metrics` endpoint.
+pub struct ServerMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+}
+
+/// Exports the counters kept in [`crate::TenantMetrics`] at scrape time.
+struct TenantCollector {
+    state: AppState,
+    descs: Vec<Desc>,
+}
+
+type TenantValue = fn(&TenantMetricsSnapshot) -> u64;
+
+// name, help, type and accessor of every per tenant metric
+const TENANT_METRICS: [(&str, &str, MetricType, TenantValue); 7] = [
+    (
+        "dino_tenant_in_flight_requests",
+        "Requests currently being handled",
+        MetricType::GAUGE,
+        |m| m.in_flight,
+    ),
+    (
+        "dino_tenant_busy_workers",
+        "JS workers currently running a handler",
+        MetricType::GAUGE,
+        |m| m.busy_workers,
+    ),
+    (
+        "dino_tenant_js_heap_bytes",
+        "Heap used by the last finished JS worker",
+        MetricType::GAUGE,
+        |m| m.heap_used,
+    ),
+    (
+        "dino_tenant_rejected_requests_total",
+        "Requests rejected by the rate or concurrency limits",
+        MetricType::COUNTER,
+        |m| m.rate_limited + m.rejected,
+    ),
+    (
+        "dino_tenant_timeouts_total",
+        "Handlers aborted by the execution timeout",
+        MetricType::COUNTER,
+        |m| m.timeouts,
+    ),
+    (
+        "dino_tenant_swaps_total",
+        "Successful code and config swaps",
+        MetricType::COUNTER,
+        |m| m.swaps,
+    ),
+    (
+        "dino_tenant_swap_failures_total",
+        "Failed code and config swaps",
+        MetricType::COUNTER,
+        |m| m.swap_failures,
+    ),
+];
+
+impl ServerMetrics {
+    pub fn try_new(state: AppState) -> Result<Self> {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("dino_http_requests_total", "HTTP requests handled"),
+            &REQUEST_LABELS,
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new("dino_http_request_duration_seconds", "HTTP request latency"),
+            &REQUEST_LABELS,
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        registry.register(Box::new(TenantCollector::try_new(state)?))?;
+        Ok(Self {
+            registry,
+            requests,
+            latency,
+        })
+    }
+
+    pub fn observe(&self, host: &str, route: &str, handler: &str, status: u16, elapsed: Duration) {
+        let status = format!("{}xx", status / 100);
+        let labels = [host, route, handler, status.as_str()];
+        self.requests.with_label_values(&labels).inc();
+        self.latency
+            .with_label_values(&labels)
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn render(&self) -> Result<String> {
+        Ok(TextEncoder::new().encode_to_string(&self.registry.gather())?)
+    }
+}
+
+impl TenantCollector {
+    fn try_new(state: AppState) -> Result<Self> {
+        let descs = TENANT_METRICS
+            .iter()
+            .map(|(name, help, _, _)| {
+                Desc::new(
+                    name.to_string(),
+                    help.to_string(),
+                    vec!["host".to_string()],
+                    Default::default(),
+                )
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { state, descs })
+    }
+}
+
+impl Collector for TenantCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let tenants = self.state.tenant_metrics();
+        TENANT_METRICS
+            .iter()
+            .map(|(name, help, kind, value)| {
+                let metrics = tenants
+                    .iter()
+                    .map(|(host, snapshot)| {
+                        let mut label = LabelPair::default();
+                        label.set_name("host".to_string());
+                        label.set_value(host.clone());
+                        let mut metric = Metric::default();
+                        metric.set_label(vec![label]);
+                        let v = value(snapshot) as f64;
+                        if *kind == MetricType::COUNTER {
+                            let mut counter = Counter::default();
+                            counter.set_value(v);
+                            metric.set_counter(counter);
+                        } else {
+                            let mut gauge = Gauge::default();
+                            gauge.set_value(v);
+                            metric.set_gauge(gauge);
+                        }
+                        metric
+                    })
+                    .collect::<Vec<_>>();
+                let mut family = MetricFamily::default();
+                family.set_name(name.to_string());
+                family.set_help(help.to_string());
+                family.set_field_type(*kind);
+                family.set_metric(metrics);
+                family
+            })
+            .collect()
+    }
+}
+
+/// Router of the admin listener.
+pub(crate) fn admin_app(metrics: Arc<ServerMetrics>) -> Router {
+    Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(metrics)
+}
+
+async fn metrics_handler(
+    State(metrics): State<Arc<ServerMetrics>>,
+) -> Result<impl IntoResponse, AppError> {
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, test_utils::test_state, MetricsLayer, ProjectConfig};
+    use axum::{body::Body, http::Request};
+    use tower::ServiceExt;
+
+    #[test]
+    fn server_metrics_should_render() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+        let state = test_state("", config)?;
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config1.yml"))?;
+        state.router.get("localhost").unwrap().swap("", config)?;
+
+        let metrics = ServerMetrics::try_new(state)?;
+        metrics.observe(
+            "localhost",
+            "/api/hello/:id",
+            "hello",
+            200,
+            Duration::from_millis(3),
+        );
+        let text = metrics.render()?;
+        assert!(text.contains(
+            r#"dino_http_requests_total{handler="hello",host="localhost",route="/api/hello/:id",status="2xx"} 1"#
+        ));
+        assert!(text.contains(r#"dino_tenant_swaps_total{host="localhost"} 1"#));
+        assert!(text.contains(r#"dino_tenant_busy_workers{host="localhost"} 0"#));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn error_responses_should_be_labelled_with_host() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+        let state = test_state("", config)?;
+        let metrics = Arc::new(ServerMetrics::try_new(state.clone())?);
+        let app = app(state).layer(MetricsLayer::new(metrics.clone()));
+
+        for host in ["localhost", "unknown"] {
+            let req = Request::get("/missing")
+                .header("host", host)
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), 404);
+        }
+        let text = metrics.render()?;
+        assert!(text.contains(
+            r#"dino_http_requests_total{handler="",host="localhost",route="",status="4xx"} 1"#
+        ));
+        assert!(text
+            .contains(r#"dino_http_requests_total{handler="",host="",route="",status="4xx"} 1"#));
+        Ok(())
+    }
+}
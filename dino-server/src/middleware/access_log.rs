@@ -0,0 +1,175 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use super::REQUEST_ID_HEADER;
+use axum::{
+    body::HttpBody,
+    extract::Request,
+    http::{header, HeaderMap},
+    response::Response,
+};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+use tracing::info;
+
+/// Settings of the access log, see [`AccessLogLayer`].
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    /// fraction of requests to log, between 0 and 1. Server errors are always logged.
+    pub sample_rate: f64,
+}
+
+/// Route and handler a request was dispatched to, attached to the response by the handler.
+#[derive(Debug, Clone)]
+pub(crate) struct RouteInfo {
+    // tenant host without port
+    pub host: String,
+    pub pattern: String,
+    pub handler: String,
+}
+
+/// Emits one structured `dino::access` event per request.
+#[derive(Clone)]
+pub struct AccessLogLayer {
+    config: AccessLogConfig,
+    counter: Arc<AtomicU64>,
+}
+
+impl<S> Layer<S> for AccessLogLayer {
+    type Service = AccessLogMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AccessLogMiddleware {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AccessLogMiddleware<S> {
+    inner: S,
+    layer: AccessLogLayer,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self { sample_rate: 1.0 }
+    }
+}
+
+impl AccessLogLayer {
+    pub fn new(config: AccessLogConfig) -> Self {
+        Self {
+            config,
+            counter: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    // spread sampled requests evenly: log the n-th request whenever n * rate crosses an integer
+    fn sampled(&self) -> bool {
+        let rate = self.config.sample_rate.clamp(0.0, 1.0);
+        let n = self.counter.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * rate).floor() > (n * rate).floor()
+    }
+}
+
+impl<S> Service<Request> for AccessLogMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let start = Instant::now();
+        let sampled = self.layer.sampled();
+        let method = request.method().clone();
+        let host = request
+            .uri()
+            .host()
+            .or_else(|| {
+                request
+                    .headers()
+                    .get(header::HOST)
+                    .and_then(|v| v.to_str().ok())
+            })
+            .unwrap_or_default()
+            .to_string();
+        let path = request.uri().path().to_string();
+        let bytes_in = body_size(request.headers(), request.body().size_hint().exact());
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            let status = response.status();
+            if !sampled && !status.is_server_error() {
+                return Ok(response);
+            }
+            let route = response.extensions().get::<RouteInfo>();
+            let bytes_out = body_size(response.headers(), response.body().size_hint().exact());
+            info!(
+                target: "dino::access",
+                method = %method,
+                host,
+                path,
+                route = route.map(|r| r.pattern.as_str()).unwrap_or_default(),
+                handler = route.map(|r| r.handler.as_str()).unwrap_or_default(),
+                status = status.as_u16(),
+                latency_us = start.elapsed().as_micros() as u64,
+                bytes_in,
+                bytes_out,
+                request_id = response
+                    .headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or_default(),
+                "{} {} {}",
+                method,
+                path,
+                status.as_u16()
+            );
+            Ok(response)
+        })
+    }
+}
+
+fn body_size(headers: &HeaderMap, exact: Option<u64>) -> u64 {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .or(exact)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_log_sampling_should_work() {
+        let layer = AccessLogLayer::new(AccessLogConfig { sample_rate: 0.25 });
+        let sampled = (0..100).filter(|_| layer.sampled()).count();
+        assert_eq!(sampled, 25);
+
+        let layer = AccessLogLayer::new(AccessLogConfig { sample_rate: 0.0 });
+        assert!(!(0..100).any(|_| layer.sampled()));
+
+        let layer = AccessLogLayer::new(AccessLogConfig::default());
+        assert!((0..100).all(|_| layer.sampled()));
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use axum::{
+    body::HttpBody,
+    http::{header, Response},
+};
+use tower_http::compression::{CompressionLayer, Predicate};
+
+use crate::CompressionConfig;
+
+/// Compresses handler responses following the [`CompressionConfig`] of their project.
+pub(crate) fn compression_layer() -> CompressionLayer<TenantCompression> {
+    CompressionLayer::new().compress_when(TenantCompression)
+}
+
+/// Reads the project's [`CompressionConfig`] from the response extensions, so responses not
+/// produced by a handler (errors, preflights) are never compressed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TenantCompression;
+
+impl Predicate for TenantCompression {
+    fn should_compress<B>(&self, response: &Response<B>) -> bool
+    where
+        B: HttpBody,
+    {
+        let Some(config) = response.extensions().get::<Arc<CompressionConfig>>() else {
+            return false;
+        };
+        if !config.enabled || response.headers().contains_key(header::CONTENT_ENCODING) {
+            return false;
+        }
+        let size = response.body().size_hint().exact().or_else(|| {
+            response
+                .headers()
+                .get(header::CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+        });
+        if size.is_some_and(|size| size < config.min_size) {
+            return false;
+        }
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        config
+            .content_types
+            .iter()
+            .any(|t| content_type.starts_with(t.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_utils::test_app, ProjectConfig};
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{header, Request},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn compression_should_follow_project_config() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: compression
+            compression:
+              min_size: 100
+              content_types: [text/plain]
+            routes:
+              /big:
+                - method: GET
+                  handler: big
+              /small:
+                - method: GET
+                  handler: small
+              /encoded:
+                - method: GET
+                  handler: encoded
+            "#,
+        )?;
+        let code = r#"
+        (function(){
+            let h={"content-type":"text/plain"};
+            async function big(req){return{status:200,headers:h,body:"a".repeat(1000)};}
+            async function small(req){return{status:200,headers:h,body:"a"};}
+            async function encoded(req){return{status:200,headers:{"content-type":"text/plain","content-encoding":"identity"},body:"a".repeat(1000)};}
+            return{big:big,small:small,encoded:encoded};
+        })();"#;
+        let app = test_app(code, config)?;
+
+        let get = |path: &str| {
+            Request::get(path)
+                .header(header::HOST, "localhost")
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+        };
+        let res = app.clone().oneshot(get("/big")?).await?;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        let res = app.clone().oneshot(get("/small")?).await?;
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        let res = app.oneshot(get("/encoded")?).await?;
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "identity");
+        Ok(())
+    }
+}
@@ -0,0 +1,207 @@
+use std::{
+    borrow::Cow,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{
+    extract::Request,
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use crate::AppState;
+
+/// Answers CORS preflights and adds CORS headers to responses, following the policy
+/// configured for the tenant's route.
+#[derive(Clone)]
+pub struct CorsLayer {
+    state: AppState,
+}
+
+impl CorsLayer {
+    pub fn new(state: AppState) -> Self {
+        Self { state }
+    }
+}
+
+impl<S> Layer<S> for CorsLayer {
+    type Service = CorsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CorsMiddleware {
+            inner,
+            state: self.state.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CorsMiddleware<S> {
+    inner: S,
+    state: AppState,
+}
+
+impl<S> Service<Request> for CorsMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        // not a cross-origin request
+        let Some(origin) = request.headers().get(header::ORIGIN).cloned() else {
+            return Box::pin(self.inner.call(request));
+        };
+        let host = request
+            .headers()
+            .get(header::HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| request.uri().host())
+            .and_then(|h| h.split(':').next())
+            .unwrap_or_default();
+        let Some(router) = self.state.router.get(host).map(|r| r.load()) else {
+            return Box::pin(self.inner.call(request));
+        };
+        // policies are looked up on the path the request is routed on
+        let path = router
+            .resolve_path(request.uri().path(), None)
+            .unwrap_or(Cow::Borrowed(request.uri().path()));
+
+        let requested = request
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| Method::from_bytes(v.as_bytes()).ok());
+        if let Some(requested) = requested.filter(|_| request.method() == Method::OPTIONS) {
+            // without a policy the preflight is left to an OPTIONS handler, if any
+            if let Some(policy) = router.cors_policy(&requested, &path) {
+                let headers =
+                    policy.preflight(&origin, request.headers(), &router.allowed_methods(&path));
+                let response = (StatusCode::NO_CONTENT, headers).into_response();
+                return Box::pin(async move { Ok(response) });
+            }
+        }
+
+        let policy = router.cors_policy(request.method(), &path).cloned();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let mut response: Response = future.await?;
+            if let Some(policy) = policy {
+                policy.decorate(&origin, response.headers_mut());
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        test_utils::{test_app, HELLO_CODE},
+        ProjectConfig,
+    };
+    use anyhow::Result;
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn cors_layer_should_answer_preflight_and_decorate_response() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: cors
+            cors:
+              origins: [https://example.com]
+            routes:
+              /api/hello/:id:
+                - method: GET
+                  handler: hello
+                - method: POST
+                  handler: hello
+                  cors:
+                    origins: ['*']
+            "#,
+        )?;
+        let app = test_app(HELLO_CODE, config)?;
+
+        let req = Request::options("/api/hello/1")
+            .header(header::HOST, "localhost")
+            .header(header::ORIGIN, "https://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_METHODS],
+            "GET, POST"
+        );
+
+        let req = Request::get("/api/hello/1")
+            .header(header::HOST, "localhost")
+            .header(header::ORIGIN, "https://evil.com")
+            .body(Body::empty())?;
+        let res = app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // the route overrides the project policy
+        let req = Request::post("/api/hello/1")
+            .header(header::HOST, "localhost")
+            .header(header::ORIGIN, "https://evil.com")
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cors_policy_should_follow_normalized_and_rewritten_paths() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: cors
+            paths:
+              trailing_slash: match
+            rewrites:
+              - from: /hello/:id
+                to: /api/hello/:id
+            routes:
+              /api/hello/:id:
+                - method: GET
+                  handler: hello
+                  cors:
+                    origins: ['*']
+            "#,
+        )?;
+        let app = test_app(HELLO_CODE, config)?;
+
+        for path in ["/api/hello/1/", "/hello/1"] {
+            let req = Request::options(path)
+                .header(header::HOST, "localhost")
+                .header(header::ORIGIN, "https://example.com")
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+                .body(Body::empty())?;
+            let res = app.clone().oneshot(req).await?;
+            assert_eq!(res.status(), StatusCode::NO_CONTENT);
+            assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use super::RouteInfo;
+use crate::ServerMetrics;
+use axum::{extract::Request, response::Response};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+/// Records request count and latency of every request into [`ServerMetrics`].
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<ServerMetrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<ServerMetrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = MetricsMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MetricsMiddleware {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsMiddleware<S> {
+    inner: S,
+    metrics: Arc<ServerMetrics>,
+}
+
+impl<S> Service<Request> for MetricsMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let start = Instant::now();
+        let metrics = self.metrics.clone();
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let response: Response = future.await?;
+            // requests to unknown hosts are recorded with empty labels to keep the cardinality
+            // bounded, unmatched paths with an empty route
+            let (host, route, handler) = match response.extensions().get::<RouteInfo>() {
+                Some(r) => (r.host.as_str(), r.pattern.as_str(), r.handler.as_str()),
+                None => ("", "", ""),
+            };
+            metrics.observe(
+                host,
+                route,
+                handler,
+                response.status().as_u16(),
+                start.elapsed(),
+            );
+            Ok(response)
+        })
+    }
+}
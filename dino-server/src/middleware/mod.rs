@@ -1,4 +1,18 @@
+mod access_log;
+mod compression;
+mod cors;
+mod metrics;
+mod request_id;
 mod server_time;
+mod trace;
+pub(crate) use access_log::RouteInfo;
+pub use access_log::{AccessLogConfig, AccessLogLayer};
+pub(crate) use compression::compression_layer;
+pub use cors::CorsLayer;
+pub use metrics::MetricsLayer;
+pub use request_id::RequestIdLayer;
 pub use server_time::ServerTimeLayer;
+pub(crate) use trace::current_trace_headers;
+pub use trace::TraceLayer;
 const SERVER_TIME_HEADER: &str = "x-server-time";
-const REQUEST_ID_HEADER: &str = "x-request-id";
+pub(crate) const REQUEST_ID_HEADER: &str = "x-request-id";
@@ -0,0 +1,109 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::REQUEST_ID_HEADER;
+use axum::{extract::Request, http::HeaderValue, response::Response};
+use tower::{Layer, Service};
+use tracing::Span;
+use uuid::Uuid;
+
+// incoming ids longer than this are replaced with a generated one
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+#[derive(Clone)]
+pub struct RequestIdLayer;
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestIdMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestIdMiddleware { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestIdMiddleware<S> {
+    inner: S,
+}
+
+impl<S> Service<Request> for RequestIdMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        let id = match request.headers().get(REQUEST_ID_HEADER) {
+            Some(v) if is_valid_id(v) => v.clone(),
+            _ => {
+                let id = HeaderValue::from_str(&Uuid::now_v7().to_string())
+                    .expect("uuid is a valid header value");
+                request.headers_mut().insert(REQUEST_ID_HEADER, id.clone());
+                id
+            }
+        };
+        // the span is opened by TraceLayer
+        Span::current().record("request_id", id.to_str().unwrap_or_default());
+        let future = self.inner.call(request);
+        Box::pin(async move {
+            let mut response: Response = future.await?;
+            response.headers_mut().insert(REQUEST_ID_HEADER, id);
+            Ok(response)
+        })
+    }
+}
+
+fn is_valid_id(v: &HeaderValue) -> bool {
+    !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN && v.to_str().is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::HeaderMap, routing::get, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/",
+                get(|headers: HeaderMap| async move {
+                    headers[REQUEST_ID_HEADER].to_str().unwrap().to_string()
+                }),
+            )
+            .layer(RequestIdLayer)
+    }
+
+    #[tokio::test]
+    async fn request_id_should_be_generated() -> anyhow::Result<()> {
+        let res = app()
+            .oneshot(Request::get("/").body(Body::empty())?)
+            .await?;
+        let id = res.headers()[REQUEST_ID_HEADER].to_str()?;
+        assert_eq!(Uuid::parse_str(id)?.get_version_num(), 7);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn request_id_should_be_kept() -> anyhow::Result<()> {
+        let req = Request::get("/")
+            .header(REQUEST_ID_HEADER, "my-id")
+            .body(Body::empty())?;
+        let res = app().oneshot(req).await?;
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "my-id");
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body, "my-id");
+        Ok(())
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::{extract::Request, http::HeaderMap, response::Response};
+use opentelemetry::{global, propagation::Extractor};
+use tower::{Layer, Service};
+use tracing::{field::Empty, info_span, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use super::RouteInfo;
+
+/// Opens the `request` span of every request, continuing the trace of an incoming
+/// `traceparent` header if there is one. The span is named after the method, and the route
+/// pattern once the request is routed, never the path, to keep the number of names bounded.
+#[derive(Clone)]
+pub struct TraceLayer;
+
+impl<S> Layer<S> for TraceLayer {
+    type Service = TraceMiddleware<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TraceMiddleware { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct TraceMiddleware<S> {
+    inner: S,
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<S> Service<Request> for TraceMiddleware<S>
+where
+    S: Service<Request, Response = Response> + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let span = info_span!(
+            "request",
+            otel.name = request.method().as_str(),
+            method = %request.method(),
+            path = request.uri().path(),
+            http.route = Empty,
+            request_id = Empty,
+            status = Empty,
+        );
+        let method = request.method().clone();
+        let parent =
+            global::get_text_map_propagator(|p| p.extract(&HeaderExtractor(request.headers())));
+        span.set_parent(parent);
+        let future = span.in_scope(|| self.inner.call(request));
+        Box::pin(
+            async move {
+                let response: Response = future.await?;
+                let span = Span::current();
+                span.record("status", response.status().as_u16());
+                let route = response.extensions().get::<RouteInfo>();
+                if let Some(route) = route.filter(|r| !r.pattern.is_empty()) {
+                    span.record("http.route", route.pattern.as_str());
+                    span.record("otel.name", format!("{} {}", method, route.pattern));
+                }
+                Ok(response)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Trace context headers (`traceparent`, `tracestate`) of the current span, to be
+/// forwarded by handlers on outgoing calls.
+pub(crate) fn current_trace_headers() -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    let cx = Span::current().context();
+    global::get_text_map_propagator(|p| p.inject_context(&cx, &mut headers));
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Telemetry;
+    use axum::{body::Body, routing::get, Router};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test]
+    async fn trace_layer_should_continue_incoming_trace() -> anyhow::Result<()> {
+        let telemetry = Telemetry::new(TracerProvider::builder().build());
+        let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let app = Router::new()
+            .route(
+                "/",
+                get(|| async { current_trace_headers()["traceparent"].clone() }),
+            )
+            .layer(TraceLayer);
+        let incoming = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        let req = Request::get("/")
+            .header("traceparent", incoming)
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        let traceparent = String::from_utf8(body.to_vec())?;
+        let parts = traceparent.split('-').collect::<Vec<_>>();
+        // same trace, new span
+        assert_eq!(parts[1], "0af7651916cd43dd8448eb211c80319c");
+        assert_ne!(parts[2], "b7ad6b7169203331");
+        Ok(())
+    }
+}
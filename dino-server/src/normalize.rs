@@ -0,0 +1,150 @@
+use std::borrow::Cow;
+
+use crate::{PathConfig, PathPolicy};
+
+// the normalized path, or None when the rule leaves it as is
+type Rule = fn(&str) -> Option<String>;
+
+/// Canonical form of `path` under the rules of `config` that are not strict, and whether a
+/// rule asking for a redirect changed it.
+pub(crate) fn canonical_path<'a>(path: &'a str, config: &PathConfig) -> (Cow<'a, str>, bool) {
+    let mut canonical = Cow::Borrowed(path);
+    let mut redirect = false;
+    let rules: [(PathPolicy, Rule); 4] = [
+        (config.percent_encoding, normalize_escapes),
+        (config.duplicate_slashes, merge_slashes),
+        (config.trailing_slash, trim_trailing_slash),
+        (config.case, lowercase),
+    ];
+    for (policy, rule) in rules {
+        if policy == PathPolicy::Strict {
+            continue;
+        }
+        if let Some(normalized) = rule(&canonical) {
+            canonical = Cow::Owned(normalized);
+            redirect |= policy == PathPolicy::Redirect;
+        }
+    }
+    (canonical, redirect)
+}
+
+// decode the escaped unreserved characters (RFC 3986 section 6.2.2.2), uppercase the others
+fn normalize_escapes(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut ret = String::with_capacity(path.len());
+    let mut changed = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok());
+        match hex
+            .filter(|_| bytes[i] == b'%')
+            .map(|h| (h, u8::from_str_radix(h, 16)))
+        {
+            Some((hex, Ok(b))) => {
+                if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                    ret.push(b as char);
+                    changed = true;
+                } else {
+                    let upper = hex.to_ascii_uppercase();
+                    changed |= upper != hex;
+                    ret.push('%');
+                    ret.push_str(&upper);
+                }
+                i += 3;
+            }
+            _ => {
+                let end = path[i + 1..].find('%').map_or(path.len(), |n| i + 1 + n);
+                ret.push_str(&path[i..end]);
+                i = end;
+            }
+        }
+    }
+    changed.then_some(ret)
+}
+
+fn merge_slashes(path: &str) -> Option<String> {
+    if !path.contains("//") {
+        return None;
+    }
+    let mut ret = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c != '/' || !ret.ends_with('/') {
+            ret.push(c);
+        }
+    }
+    Some(ret)
+}
+
+fn trim_trailing_slash(path: &str) -> Option<String> {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.is_empty() {
+        // the root keeps its slash
+        true => (path.len() > 1).then(|| "/".to_string()),
+        false => (trimmed.len() < path.len()).then(|| trimmed.to_string()),
+    }
+}
+
+// ascii lowercase outside of the escapes, whose hex digits are up to `percent_encoding`
+fn lowercase(path: &str) -> Option<String> {
+    if !path.bytes().any(|b| b.is_ascii_uppercase()) {
+        return None;
+    }
+    let mut ret = String::with_capacity(path.len());
+    let mut escape = 0;
+    for c in path.chars() {
+        if c == '%' {
+            escape = 2;
+            ret.push(c);
+        } else if escape > 0 && c.is_ascii_hexdigit() {
+            escape -= 1;
+            ret.push(c);
+        } else {
+            escape = 0;
+            ret.push(c.to_ascii_lowercase());
+        }
+    }
+    (ret != path).then_some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_path_should_apply_non_strict_rules() {
+        let config = PathConfig {
+            trailing_slash: PathPolicy::Match,
+            duplicate_slashes: PathPolicy::Redirect,
+            percent_encoding: PathPolicy::Match,
+            case: PathPolicy::Redirect,
+        };
+        let canonical = |path| {
+            let (path, redirect) = canonical_path(path, &config);
+            (path.into_owned(), redirect)
+        };
+        assert_eq!(canonical("/api/users"), ("/api/users".into(), false));
+        assert_eq!(canonical("/api/users/"), ("/api/users".into(), false));
+        assert_eq!(canonical("/api//users//"), ("/api/users".into(), true));
+        assert_eq!(
+            canonical("/api/%75sers/a%2fb"),
+            ("/api/users/a%2Fb".into(), false)
+        );
+        assert_eq!(canonical("/%e4%b8%ad%"), ("/%E4%B8%AD%".into(), false));
+        assert_eq!(canonical("//"), ("/".into(), true));
+        assert_eq!(
+            canonical("/API/Users/%c3%a9"),
+            ("/api/users/%C3%A9".into(), true)
+        );
+
+        let (path, _) = canonical_path("/api/users/", &PathConfig::default());
+        assert_eq!(path, "/api/users/");
+        let config = PathConfig {
+            case: PathPolicy::Match,
+            ..Default::default()
+        };
+        let (path, _) = canonical_path("/Api/A%2fB", &config);
+        assert_eq!(path, "/api/a%2fb");
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    flatten_routes, redirect::pattern_params, AuthConfig, ParamConfig, ParamType, ProjectConfig,
+    ProjectRoute, RouteMethod, SchemaRef,
+};
+
+// methods an OpenAPI path item can describe
+const OPERATIONS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// OpenAPI 3.1 document describing the js routes of a project: their path params, the
+/// schemas declared on them and their descriptions. Extension methods are left out.
+pub fn openapi_document(config: &ProjectConfig) -> Result<Value> {
+    let mut security_schemes = Map::new();
+    let mut operation_ids = HashSet::new();
+    let mut paths = Map::new();
+    let routes = flatten_routes(config.routes.clone(), config.groups.clone());
+    for (path, routes) in &routes {
+        let mut item = Map::new();
+        for route in routes {
+            let methods = match &route.method {
+                RouteMethod::Methods(methods) => methods
+                    .iter()
+                    .map(|m| m.as_str().to_lowercase())
+                    .collect::<Vec<_>>(),
+                // methods with a handler of their own are described by it
+                RouteMethod::Any => OPERATIONS.iter().map(|m| m.to_string()).collect(),
+            };
+            for method in methods {
+                if !OPERATIONS.contains(&method.as_str())
+                    || (route.method == RouteMethod::Any && item.contains_key(&method))
+                {
+                    continue;
+                }
+                let mut operation = operation(path, route, &mut security_schemes);
+                let mut id = route.handler.clone();
+                let mut n = 1;
+                while !operation_ids.insert(id.clone()) {
+                    n += 1;
+                    id = format!("{}_{}", route.handler, n);
+                }
+                operation.insert("operationId".into(), id.into());
+                item.insert(method, operation.into());
+            }
+        }
+        paths.insert(openapi_path(path), item.into());
+    }
+
+    let mut doc = json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": config.openapi.title.as_deref().unwrap_or(&config.name),
+            "version": config.openapi.version,
+        },
+        "paths": paths,
+    });
+    if let Some(description) = &config.openapi.description {
+        doc["info"]["description"] = description.as_str().into();
+    }
+    if let Some(auth) = &config.auth {
+        doc["security"] = security(auth, &mut security_schemes);
+    }
+    let mut components = Map::new();
+    if !config.schemas.is_empty() {
+        components.insert("schemas".into(), json!(config.schemas));
+    }
+    if !security_schemes.is_empty() {
+        components.insert("securitySchemes".into(), security_schemes.into());
+    }
+    if !components.is_empty() {
+        doc["components"] = components.into();
+    }
+    Ok(doc)
+}
+
+fn operation(
+    path: &str,
+    route: &ProjectRoute,
+    security_schemes: &mut Map<String, Value>,
+) -> Map<String, Value> {
+    let options = &route.options;
+    let schema = options.schema.clone().unwrap_or_default();
+    let mut operation = Map::new();
+    if let Some(description) = &route.description {
+        operation.insert("description".into(), description.as_str().into());
+    }
+
+    let params_schema = schema.params.as_ref().map(schema_ref);
+    let mut parameters = pattern_params(path)
+        .map(|name| {
+            let schema = params_schema
+                .as_ref()
+                .and_then(|s| s["properties"].get(name))
+                .cloned()
+                .or_else(|| options.params.get(name).map(param_schema))
+                .unwrap_or_else(|| json!({ "type": "string" }));
+            json!({ "name": name, "in": "path", "required": true, "schema": schema })
+        })
+        .collect::<Vec<_>>();
+    for (location, schema) in [("query", &schema.query), ("header", &schema.headers)] {
+        let Some(schema) = schema.as_ref().map(schema_ref) else {
+            continue;
+        };
+        let required = schema["required"].as_array().cloned().unwrap_or_default();
+        let Some(properties) = schema["properties"].as_object() else {
+            continue;
+        };
+        for (name, schema) in properties {
+            parameters.push(json!({
+                "name": name,
+                "in": location,
+                "required": required.contains(&name.as_str().into()),
+                "schema": schema,
+            }));
+        }
+    }
+    if !parameters.is_empty() {
+        operation.insert("parameters".into(), parameters.into());
+    }
+
+    if let Some(body) = &schema.body {
+        let content_type = options
+            .content_type
+            .as_deref()
+            .unwrap_or("application/json");
+        operation.insert(
+            "requestBody".into(),
+            json!({ "required": true, "content": { content_type: { "schema": schema_ref(body) } } }),
+        );
+    }
+    if let Some(auth) = &route.auth {
+        operation.insert("security".into(), security(auth, security_schemes));
+    }
+    operation.insert(
+        "responses".into(),
+        json!({ "default": { "description": "Response of the handler" } }),
+    );
+    operation
+}
+
+// `/api/:id/*rest` to `/api/{id}/{rest}`
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix([':', '*']) {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn schema_ref(schema: &SchemaRef) -> Value {
+    match schema {
+        SchemaRef::Name(name) => json!({ "$ref": format!("#/components/schemas/{}", name) }),
+        SchemaRef::Inline(schema) => schema.clone(),
+    }
+}
+
+fn param_schema(config: &ParamConfig) -> Value {
+    let (kind, regex, values) = match config {
+        ParamConfig::Type(kind) => (*kind, None, None),
+        ParamConfig::Constraint {
+            kind,
+            regex,
+            values,
+            ..
+        } => (*kind, regex.as_ref(), values.as_ref()),
+    };
+    let mut schema = match kind {
+        ParamType::String => json!({ "type": "string" }),
+        ParamType::Int => json!({ "type": "integer" }),
+        ParamType::Number => json!({ "type": "number" }),
+        ParamType::Bool => json!({ "type": "boolean" }),
+        ParamType::Uuid => json!({ "type": "string", "format": "uuid" }),
+    };
+    if let Some(regex) = regex {
+        schema["pattern"] = format!("^(?:{})$", regex).into();
+    }
+    if let Some(values) = values {
+        schema["enum"] = json!(values);
+    }
+    schema
+}
+
+// security requirement of `auth`, adding its scheme to `schemes`
+fn security(auth: &AuthConfig, schemes: &mut Map<String, Value>) -> Value {
+    let (name, scheme) = match auth {
+        AuthConfig::Jwt(_) => (
+            "jwt",
+            json!({ "type": "http", "scheme": "bearer", "bearerFormat": "JWT" }),
+        ),
+        AuthConfig::ApiKey(config) => (
+            "api_key",
+            json!({ "type": "apiKey", "in": "header", "name": config.header }),
+        ),
+        AuthConfig::Basic(_) => ("basic", json!({ "type": "http", "scheme": "basic" })),
+        AuthConfig::None => return json!([]),
+    };
+    schemes.insert(name.into(), scheme);
+    json!([{ name: [] }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openapi_document_should_describe_routes() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: users
+            auth:
+              type: api_key
+              keys: { ci: abc }
+            schemas:
+              User:
+                type: object
+                properties:
+                  name: { type: string }
+            routes:
+              /api/users/:id:
+                - method: [GET, HEAD]
+                  handler: getUser
+                  description: Get a user
+                  params:
+                    id: int
+                - method: PUT
+                  handler: updateUser
+                  auth: { type: none }
+                  schema:
+                    query:
+                      type: object
+                      required: [dry]
+                      properties:
+                        dry: { type: boolean }
+                    body: User
+            "#,
+        )?;
+        let doc = openapi_document(&config)?;
+        assert_eq!(doc["info"]["title"], "users");
+        let item = &doc["paths"]["/api/users/{id}"];
+        assert_eq!(item["get"]["operationId"], "getUser");
+        assert_eq!(item["head"]["operationId"], "getUser_2");
+        assert_eq!(item["get"]["description"], "Get a user");
+        assert_eq!(item["get"]["parameters"][0]["schema"]["type"], "integer");
+
+        let put = &item["put"];
+        assert_eq!(put["parameters"][1]["name"], "dry");
+        assert_eq!(put["parameters"][1]["required"], true);
+        assert_eq!(
+            put["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/User"
+        );
+        assert_eq!(put["security"], json!([]));
+        assert_eq!(doc["security"], json!([{ "api_key": [] }]));
+        assert_eq!(
+            doc["components"]["securitySchemes"]["api_key"]["name"],
+            "x-api-key"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+use anyhow::{anyhow, Result};
+use axum::http::StatusCode;
+use regex::Regex;
+use rquickjs::{Ctx, IntoJs, Value};
+
+use crate::{ParamConfig, ParamType};
+
+/// A path param passed to handlers, converted to the type declared for it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Param {
+    String(String),
+    Int(i64),
+    Number(f64),
+    Bool(bool),
+}
+
+/// A [`ParamConfig`] with its regex compiled.
+#[derive(Debug, Clone)]
+pub struct ParamConstraint {
+    kind: ParamType,
+    regex: Option<Regex>,
+    values: Option<Vec<String>>,
+    /// status of the requests whose value is rejected
+    pub status: StatusCode,
+}
+
+impl ParamConstraint {
+    pub fn try_new(config: &ParamConfig) -> Result<Self> {
+        let (kind, regex, values, status) = match config {
+            ParamConfig::Type(kind) => (*kind, None, None, 404),
+            ParamConfig::Constraint {
+                kind,
+                regex,
+                values,
+                status,
+            } => (*kind, regex.as_ref(), values.clone(), *status),
+        };
+        let status = match status {
+            404 | 400 => StatusCode::from_u16(status)?,
+            _ => return Err(anyhow!("param status must be 404 or 400, got {}", status)),
+        };
+        // the whole value has to match
+        let regex = regex
+            .map(|r| Regex::new(&format!("^(?:{})$", r)))
+            .transpose()?;
+        Ok(Self {
+            kind,
+            regex,
+            values,
+            status,
+        })
+    }
+
+    /// Convert `value`, or tell why it is rejected.
+    pub fn check(&self, value: &str) -> Result<Param, String> {
+        if let Some(regex) = &self.regex {
+            if !regex.is_match(value) {
+                return Err(format!("expect a value matching {}", regex));
+            }
+        }
+        if let Some(values) = &self.values {
+            if !values.iter().any(|v| v == value) {
+                return Err(format!("expect one of {}", values.join(", ")));
+            }
+        }
+        let param = match self.kind {
+            ParamType::String => Some(Param::String(value.to_string())),
+            ParamType::Int => value.parse().ok().map(Param::Int),
+            ParamType::Number => value
+                .parse()
+                .ok()
+                .filter(|v: &f64| v.is_finite())
+                .map(Param::Number),
+            ParamType::Bool => value.parse().ok().map(Param::Bool),
+            ParamType::Uuid => uuid::Uuid::parse_str(value)
+                .ok()
+                .map(|_| Param::String(value.to_string())),
+        };
+        param.ok_or_else(|| format!("expect {:?}", self.kind).to_lowercase())
+    }
+}
+
+impl From<&Param> for serde_json::Value {
+    fn from(param: &Param) -> Self {
+        match param {
+            Param::String(v) => v.as_str().into(),
+            Param::Int(v) => (*v).into(),
+            Param::Number(v) => (*v).into(),
+            Param::Bool(v) => (*v).into(),
+        }
+    }
+}
+
+impl<'js> IntoJs<'js> for Param {
+    fn into_js(self, ctx: &Ctx<'js>) -> rquickjs::Result<Value<'js>> {
+        match self {
+            Param::String(v) => v.into_js(ctx),
+            // js numbers are doubles
+            Param::Int(v) => (v as f64).into_js(ctx),
+            Param::Number(v) => v.into_js(ctx),
+            Param::Bool(v) => v.into_js(ctx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn param_constraint_should_check_and_convert() -> Result<()> {
+        let int = ParamConstraint::try_new(&serde_yaml::from_str("int")?)?;
+        assert_eq!(int.check("42"), Ok(Param::Int(42)));
+        assert!(int.check("abc").is_err());
+        assert_eq!(int.status, StatusCode::NOT_FOUND);
+
+        let uuid = ParamConstraint::try_new(&serde_yaml::from_str("uuid")?)?;
+        assert!(uuid.check("67e55044-10b1-426f-9247-bb680e5fe0c8").is_ok());
+        assert!(uuid.check("67e55044").is_err());
+
+        let slug =
+            ParamConstraint::try_new(&serde_yaml::from_str("{ regex: '[a-z-]+', status: 400 }")?)?;
+        assert_eq!(
+            slug.check("hello-world"),
+            Ok(Param::String("hello-world".into()))
+        );
+        assert!(slug.check("Hello").is_err());
+        assert_eq!(slug.status, StatusCode::BAD_REQUEST);
+
+        let kind = ParamConstraint::try_new(&serde_yaml::from_str("{ enum: [a, b] }")?)?;
+        assert!(kind.check("a").is_ok());
+        assert!(kind.check("c").is_err());
+        Ok(())
+    }
+}
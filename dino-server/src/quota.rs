@@ -0,0 +1,476 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc,
+};
+
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+use dashmap::DashMap;
+use tokio::{
+    runtime::Handle,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
+
+use crate::{AppError, ProjectLimits, RateLimitConfig, RateLimitKey};
+
+// buckets kept per limiter before the idle ones are first dropped
+const MAX_RATE_LIMIT_KEYS: usize = 10_000;
+
+/// Enforces the `limits` section of a tenant's config.
+pub struct TenantQuota {
+    limits: ProjectLimits,
+    requests: Option<Arc<Semaphore>>,
+    workers: Option<Arc<Semaphore>>,
+    // `limits.rate_limit`, a single bucket shared by every request of the tenant
+    rate_limit: Option<Arc<KeyedRateLimiter>>,
+}
+
+/// A `rate_limit` section of a tenant's config, with one token bucket per key. Buckets live in
+/// memory, and are kept across swaps leaving the config of the limiter unchanged.
+#[derive(Debug)]
+pub struct KeyedRateLimiter {
+    config: RateLimitConfig,
+    buckets: DashMap<String, TokenBucket>,
+    // number of buckets at which the idle ones are dropped
+    next_sweep: AtomicUsize,
+}
+
+/// State of a key's bucket after a request, sent back as `RateLimit-*` headers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// seconds until the bucket is full again
+    pub reset: u64,
+    /// seconds until the next request is allowed, set when the request was rejected
+    pub retry_after: Option<u64>,
+}
+
+/// Counters of a tenant, kept across code swaps.
+#[derive(Debug, Default)]
+pub struct TenantMetrics {
+    requests: AtomicU64,
+    in_flight: AtomicU64,
+    rate_limited: AtomicU64,
+    rejected: AtomicU64,
+    timeouts: AtomicU64,
+    max_heap_used: AtomicU64,
+    heap_used: AtomicU64,
+    busy_workers: AtomicU64,
+    swaps: AtomicU64,
+    swap_failures: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TenantMetricsSnapshot {
+    pub requests: u64,
+    pub in_flight: u64,
+    pub rate_limited: u64,
+    pub rejected: u64,
+    pub timeouts: u64,
+    pub max_heap_used: u64,
+    /// heap used by the last finished js worker
+    pub heap_used: u64,
+    pub busy_workers: u64,
+    pub swaps: u64,
+    pub swap_failures: u64,
+}
+
+/// Marks a js worker as busy until dropped.
+pub struct BusyWorkerGuard {
+    metrics: Arc<TenantMetrics>,
+}
+
+/// Permits held while a request is being handled, released on drop.
+pub struct RequestGuard {
+    metrics: Arc<TenantMetrics>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    // tokens refilled per second
+    rate: f64,
+    last: Instant,
+}
+
+impl TenantQuota {
+    pub fn new(limits: ProjectLimits) -> Self {
+        let requests = limits
+            .max_concurrent_requests
+            .map(|n| Arc::new(Semaphore::new(n)));
+        let workers = limits.workers.map(|n| Arc::new(Semaphore::new(n)));
+        let rate_limit = limits.rate_limit.map(|requests| {
+            Arc::new(KeyedRateLimiter::new(RateLimitConfig {
+                requests,
+                period: 1,
+                key: RateLimitKey::default(),
+            }))
+        });
+        Self {
+            limits,
+            requests,
+            workers,
+            rate_limit,
+        }
+    }
+
+    /// The quota of `limits` for the new code or config of the tenant. The permits held by the
+    /// requests in flight count against the new limits, and the rate limit keeps its bucket if
+    /// it is unchanged.
+    pub fn resize(&self, limits: ProjectLimits) -> Self {
+        let mut quota = Self::new(limits);
+        let old = &self.limits;
+        quota.requests = resize_semaphore(
+            &self.requests,
+            old.max_concurrent_requests,
+            quota.limits.max_concurrent_requests,
+        );
+        quota.workers = resize_semaphore(&self.workers, old.workers, quota.limits.workers);
+        if old.rate_limit == quota.limits.rate_limit {
+            quota.rate_limit = self.rate_limit.clone();
+        }
+        quota
+    }
+
+    pub fn limits(&self) -> &ProjectLimits {
+        &self.limits
+    }
+
+    /// Admit a new request, or reject it if the tenant is over its rate or concurrency limit.
+    pub fn admit(&self, metrics: &Arc<TenantMetrics>) -> Result<RequestGuard, AppError> {
+        metrics.requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(rate_limit) = &self.rate_limit {
+            rate_limit.check("", metrics)?;
+        }
+        let permit = match &self.requests {
+            Some(sem) => match sem.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(AppError::ConcurrencyLimited);
+                }
+            },
+            None => None,
+        };
+        metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(RequestGuard {
+            metrics: metrics.clone(),
+            _permit: permit,
+        })
+    }
+
+    /// Wait for a free js worker slot.
+    pub async fn acquire_worker(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.workers {
+            Some(sem) => sem.clone().acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+}
+
+// the semaphore of a limit going from `from` to `to` permits, the same one when both are set
+fn resize_semaphore(
+    sem: &Option<Arc<Semaphore>>,
+    from: Option<usize>,
+    to: Option<usize>,
+) -> Option<Arc<Semaphore>> {
+    let (Some(sem), Some(from), Some(to)) = (sem, from, to) else {
+        return to.map(|n| Arc::new(Semaphore::new(n)));
+    };
+    if to >= from {
+        sem.add_permits(to - from);
+        return Some(sem.clone());
+    }
+    // permits held in flight are forgotten once released
+    let held = from - to - sem.forget_permits(from - to);
+    if let (Ok(handle), Ok(held)) = (Handle::try_current(), u32::try_from(held)) {
+        let sem = sem.clone();
+        handle.spawn(async move {
+            if let Ok(permits) = sem.acquire_many_owned(held).await {
+                permits.forget();
+            }
+        });
+    }
+    Some(sem.clone())
+}
+
+impl KeyedRateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: DashMap::new(),
+            next_sweep: AtomicUsize::new(MAX_RATE_LIMIT_KEYS),
+        }
+    }
+
+    pub fn config(&self) -> &RateLimitConfig {
+        &self.config
+    }
+
+    pub fn key(&self) -> &RateLimitKey {
+        &self.config.key
+    }
+
+    /// Take a token from the bucket of `key`, or reject the request with
+    /// [`AppError::RateLimitExceeded`] if it is empty.
+    pub fn check(&self, key: &str, metrics: &TenantMetrics) -> Result<RateLimitStatus, AppError> {
+        let next_sweep = self.next_sweep.load(Ordering::Relaxed);
+        if self.buckets.len() >= next_sweep && !self.buckets.contains_key(key) {
+            self.buckets.retain(|_, bucket| !bucket.is_full());
+            // the buckets left are busy, sweeping again only once they doubled keeps the
+            // cost of the sweeps constant per new key
+            let next_sweep = (self.buckets.len() * 2).max(MAX_RATE_LIMIT_KEYS);
+            self.next_sweep.store(next_sweep, Ordering::Relaxed);
+        }
+        let capacity = self.config.requests as f64;
+        let rate = capacity / self.config.period.max(1) as f64;
+        let mut bucket = self
+            .buckets
+            .entry(key.to_string())
+            .or_insert_with(|| TokenBucket::new(capacity, rate));
+        let allowed = bucket.try_take();
+        let status = RateLimitStatus {
+            limit: self.config.requests,
+            remaining: bucket.tokens as u32,
+            reset: bucket.secs_until(capacity),
+            retry_after: (!allowed).then(|| bucket.secs_until(1.0).max(1)),
+        };
+        if allowed {
+            Ok(status)
+        } else {
+            metrics.rate_limited.fetch_add(1, Ordering::Relaxed);
+            Err(AppError::RateLimitExceeded(status))
+        }
+    }
+}
+
+impl RateLimitStatus {
+    /// The status with the fewest requests left.
+    pub fn tightest(a: Option<Self>, b: Self) -> Self {
+        match a {
+            Some(a) if a.remaining <= b.remaining => a,
+            _ => b,
+        }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        headers.insert(
+            HeaderName::from_static("ratelimit-limit"),
+            HeaderValue::from(self.limit),
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit-remaining"),
+            HeaderValue::from(self.remaining),
+        );
+        headers.insert(
+            HeaderName::from_static("ratelimit-reset"),
+            HeaderValue::from(self.reset),
+        );
+        if let Some(retry_after) = self.retry_after {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+    }
+}
+
+impl TenantMetrics {
+    pub fn record_timeout(&self) {
+        self.timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_heap_used(&self, size: u64) {
+        self.heap_used.store(size, Ordering::Relaxed);
+        self.max_heap_used.fetch_max(size, Ordering::Relaxed);
+    }
+
+    pub fn record_swap(&self) {
+        self.swaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_swap_failure(&self) {
+        self.swap_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn worker_busy(self: &Arc<Self>) -> BusyWorkerGuard {
+        self.busy_workers.fetch_add(1, Ordering::Relaxed);
+        BusyWorkerGuard {
+            metrics: self.clone(),
+        }
+    }
+
+    pub fn snapshot(&self) -> TenantMetricsSnapshot {
+        TenantMetricsSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            rate_limited: self.rate_limited.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+            max_heap_used: self.max_heap_used.load(Ordering::Relaxed),
+            heap_used: self.heap_used.load(Ordering::Relaxed),
+            busy_workers: self.busy_workers.load(Ordering::Relaxed),
+            swaps: self.swaps.load(Ordering::Relaxed),
+            swap_failures: self.swap_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for BusyWorkerGuard {
+    fn drop(&mut self) {
+        self.metrics.busy_workers.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl TokenBucket {
+    pub(crate) fn new(capacity: f64, rate: f64) -> Self {
+        Self {
+            capacity,
+            tokens: capacity,
+            rate,
+            last: Instant::now(),
+        }
+    }
+
+    pub(crate) fn try_take(&mut self) -> bool {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket would be full by now, i.e. it has not been used for a while.
+    fn is_full(&self) -> bool {
+        self.tokens + self.last.elapsed().as_secs_f64() * self.rate >= self.capacity
+    }
+
+    // seconds until the bucket holds `tokens`, as of the last refill
+    fn secs_until(&self, tokens: f64) -> u64 {
+        ((tokens - self.tokens).max(0.0) / self.rate).ceil() as u64
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quota_should_reject_over_concurrency() {
+        let limits = ProjectLimits {
+            max_concurrent_requests: Some(1),
+            ..Default::default()
+        };
+        let quota = TenantQuota::new(limits);
+        let metrics = Arc::new(TenantMetrics::default());
+        let guard = quota.admit(&metrics).unwrap();
+        assert!(matches!(
+            quota.admit(&metrics),
+            Err(AppError::ConcurrencyLimited)
+        ));
+        assert_eq!(metrics.snapshot().in_flight, 1);
+        drop(guard);
+        assert_eq!(metrics.snapshot().in_flight, 0);
+        assert!(quota.admit(&metrics).is_ok());
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.requests, 3);
+        assert_eq!(snapshot.rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn resized_quota_should_count_requests_in_flight() {
+        let limits = |n| ProjectLimits {
+            max_concurrent_requests: Some(n),
+            ..Default::default()
+        };
+        let metrics = Arc::new(TenantMetrics::default());
+        let quota = TenantQuota::new(limits(1));
+        let first = quota.admit(&metrics).unwrap();
+
+        let quota = quota.resize(limits(2));
+        let second = quota.admit(&metrics).unwrap();
+        assert!(quota.admit(&metrics).is_err());
+
+        // both permits are in flight, the first released is forgotten
+        let quota = quota.resize(limits(1));
+        drop(first);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        assert!(quota.admit(&metrics).is_err());
+        drop(second);
+        assert!(quota.admit(&metrics).is_ok());
+    }
+
+    #[test]
+    fn quota_should_reject_over_rate() {
+        let limits = ProjectLimits {
+            rate_limit: Some(2),
+            ..Default::default()
+        };
+        let quota = TenantQuota::new(limits);
+        let metrics = Arc::new(TenantMetrics::default());
+        assert!(quota.admit(&metrics).is_ok());
+        assert!(quota.admit(&metrics).is_ok());
+        let Err(AppError::RateLimitExceeded(status)) = quota.admit(&metrics) else {
+            panic!("expect the third request to be rate limited");
+        };
+        assert_eq!(status.limit, 2);
+        assert_eq!(status.retry_after, Some(1));
+        assert_eq!(metrics.snapshot().rate_limited, 1);
+    }
+
+    #[test]
+    fn keyed_rate_limiter_should_limit_each_key() {
+        let limiter = KeyedRateLimiter::new(RateLimitConfig {
+            requests: 2,
+            period: 60,
+            key: RateLimitKey::Ip,
+        });
+        let metrics = TenantMetrics::default();
+        let status = limiter.check("1.1.1.1", &metrics).unwrap();
+        assert_eq!((status.limit, status.remaining), (2, 1));
+        assert!(limiter.check("1.1.1.1", &metrics).is_ok());
+        let Err(AppError::RateLimitExceeded(status)) = limiter.check("1.1.1.1", &metrics) else {
+            panic!("expect the third request to be limited");
+        };
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.retry_after, Some(30));
+        assert!(limiter.check("2.2.2.2", &metrics).is_ok());
+        assert_eq!(metrics.snapshot().rate_limited, 1);
+    }
+
+    #[test]
+    fn keyed_rate_limiter_should_sweep_idle_buckets() {
+        let limiter = KeyedRateLimiter::new(RateLimitConfig {
+            requests: 1,
+            period: 60,
+            key: RateLimitKey::Ip,
+        });
+        let metrics = TenantMetrics::default();
+        for i in 0..MAX_RATE_LIMIT_KEYS {
+            assert!(limiter.check(&i.to_string(), &metrics).is_ok());
+        }
+        // every bucket is in use, the next sweep waits for twice as many
+        assert!(limiter.check("new", &metrics).is_ok());
+        assert_eq!(limiter.buckets.len(), MAX_RATE_LIMIT_KEYS + 1);
+        assert_eq!(
+            limiter.next_sweep.load(Ordering::Relaxed),
+            MAX_RATE_LIMIT_KEYS * 2
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use axum::http::StatusCode;
+use matchit::{Params, Router};
+
+use crate::{RedirectConfig, RewriteConfig};
+
+/// The `redirects` and `rewrites` of a project, resolved before a js handler is matched.
+#[derive(Default)]
+pub struct Redirects {
+    redirects: Router<(StatusCode, Target)>,
+    rewrites: Router<Target>,
+}
+
+/// What to do with a request matching a redirect or a rewrite.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Redirected {
+    /// answer with the status and this `Location`
+    Redirect(StatusCode, String),
+    /// route the request as if it was sent to this path
+    Rewrite(String),
+}
+
+// a path or url where `:name` and `*name` segments are replaced by the params of the match
+#[derive(Debug)]
+struct Target(String);
+
+impl Redirects {
+    pub fn try_new(redirects: &[RedirectConfig], rewrites: &[RewriteConfig]) -> Result<Self> {
+        let mut ret = Self::default();
+        for config in redirects {
+            let status = match config.status {
+                Some(status @ (301 | 302 | 303 | 307 | 308)) => StatusCode::from_u16(status)?,
+                Some(status) => return Err(anyhow!("{} is not a redirect status", status)),
+                None if config.permanent => StatusCode::PERMANENT_REDIRECT,
+                None => StatusCode::TEMPORARY_REDIRECT,
+            };
+            let target = Target::try_new(&config.from, &config.to)?;
+            ret.redirects.insert(&config.from, (status, target))?;
+        }
+        for config in rewrites {
+            let target = Target::try_new(&config.from, &config.to)?;
+            if !target.0.starts_with('/') {
+                return Err(anyhow!("rewrite target {} must be a path", config.to));
+            }
+            ret.rewrites.insert(&config.from, target)?;
+        }
+        Ok(ret)
+    }
+
+    /// Redirect or rewrite a request to `path`. The query is kept unless the redirect target
+    /// has its own.
+    pub fn resolve(&self, path: &str, query: Option<&str>) -> Option<Redirected> {
+        if let Ok(m) = self.redirects.at(path) {
+            let (status, target) = m.value;
+            let mut location = target.render(&m.params);
+            if let Some(query) = query.filter(|_| !location.contains('?')) {
+                location = format!("{}?{}", location, query);
+            }
+            return Some(Redirected::Redirect(*status, location));
+        }
+        let m = self.rewrites.at(path).ok()?;
+        Some(Redirected::Rewrite(m.value.render(&m.params)))
+    }
+}
+
+impl Target {
+    fn try_new(from: &str, to: &str) -> Result<Self> {
+        let known = pattern_params(from).collect::<Vec<_>>();
+        if let Some(name) = pattern_params(to).find(|name| !known.contains(name)) {
+            return Err(anyhow!("{} uses param {} not found in {}", to, name, from));
+        }
+        Ok(Self(to.to_string()))
+    }
+
+    fn render(&self, values: &Params) -> String {
+        let rendered = self
+            .0
+            .split('/')
+            .map(|segment| match param(segment) {
+                Some((name, rest)) => format!("{}{}", values.get(name).unwrap_or_default(), rest),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        if !self.0.starts_with('/') {
+            return rendered;
+        }
+        // a param starting with a slash must not turn the path into `//host`
+        same_host_path(&rendered)
+    }
+}
+
+/// `path` with its leading run of `/` and `\` collapsed into one slash, as browsers follow a
+/// `Location` of `//host` or `/\host` to another host.
+pub(crate) fn same_host_path(path: &str) -> String {
+    format!("/{}", path.trim_start_matches(['/', '\\']))
+}
+
+/// Names of the `:name` and `*name` params of a route pattern.
+pub(crate) fn pattern_params(pattern: &str) -> impl Iterator<Item = &str> {
+    pattern
+        .split('/')
+        .filter_map(|segment| param(segment).map(|(name, _)| name))
+}
+
+// name of the param of a segment and what follows it, e.g. a query in `*page?ref=dino`
+fn param(segment: &str) -> Option<(&str, &str)> {
+    let name = segment.strip_prefix([':', '*'])?;
+    let end = name
+        .find(|c: char| !c.is_alphanumeric() && c != '_')
+        .unwrap_or(name.len());
+    Some(name.split_at(end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redirects_should_substitute_params() -> Result<()> {
+        let redirects: Vec<RedirectConfig> = serde_yaml::from_str(
+            r#"
+            - from: /old/:id
+              to: /new/:id
+              permanent: true
+            - from: /docs/*page
+              to: https://docs.example.com/*page?ref=dino
+              status: 302
+            "#,
+        )?;
+        let rewrites: Vec<RewriteConfig> =
+            serde_yaml::from_str("- from: /blog/:slug\n  to: /api/posts/:slug\n")?;
+        let redirects = Redirects::try_new(&redirects, &rewrites)?;
+
+        assert_eq!(
+            redirects.resolve("/old/1", Some("a=b")),
+            Some(Redirected::Redirect(
+                StatusCode::PERMANENT_REDIRECT,
+                "/new/1?a=b".into()
+            ))
+        );
+        assert_eq!(
+            redirects.resolve("/docs/guide/intro", Some("a=b")),
+            Some(Redirected::Redirect(
+                StatusCode::FOUND,
+                "https://docs.example.com/guide/intro?ref=dino".into()
+            ))
+        );
+        assert_eq!(
+            redirects.resolve("/blog/hello", None),
+            Some(Redirected::Rewrite("/api/posts/hello".into()))
+        );
+        assert_eq!(redirects.resolve("/api/posts/hello", None), None);
+
+        let open: Vec<RedirectConfig> = serde_yaml::from_str("- from: /go/*rest\n  to: /*rest\n")?;
+        let open = Redirects::try_new(&open, &[])?;
+        for path in ["/go//evil.com", "/go/\\evil.com"] {
+            assert_eq!(
+                open.resolve(path, None),
+                Some(Redirected::Redirect(
+                    StatusCode::TEMPORARY_REDIRECT,
+                    "/evil.com".into()
+                ))
+            );
+        }
+
+        let bad: Vec<RewriteConfig> = serde_yaml::from_str("- from: /a/:id\n  to: /b/:name\n")?;
+        assert!(Redirects::try_new(&[], &bad).is_err());
+        Ok(())
+    }
+}
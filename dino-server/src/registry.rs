@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{ProjectConfig, SwappableAppRouter, TenentRouter};
+
+/// A storage backend that remembers which tenants are deployed, so they can be
+/// replayed when the server restarts.
+pub trait TenantRegistry: Send + Sync {
+    fn load(&self) -> Result<Vec<TenantRecord>>;
+    fn save(&self, record: &TenantRecord) -> Result<()>;
+    fn remove(&self, host: &str) -> Result<()>;
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TenantRecord {
+    pub host: String,
+    /// bundled js code of the tenant
+    pub code: String,
+    /// raw content of the tenant's config.yml
+    pub config: String,
+}
+
+/// Default registry which keeps all records in a single json file.
+pub struct FileRegistry {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl TenantRecord {
+    pub fn new(
+        host: impl Into<String>,
+        code: impl Into<String>,
+        config: impl Into<String>,
+    ) -> Self {
+        Self {
+            host: host.into(),
+            code: code.into(),
+            config: config.into(),
+        }
+    }
+
+    pub fn project_config(&self) -> Result<ProjectConfig> {
+        ProjectConfig::from_yaml(&self.config)
+    }
+
+    pub fn to_router(&self) -> Result<TenentRouter> {
+        let config = self.project_config()?;
+        let router = SwappableAppRouter::try_new(&self.code, config)?;
+        Ok(TenentRouter::new(&self.host, router))
+    }
+}
+
+impl FileRegistry {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Result<IndexMap<String, TenantRecord>> {
+        if !self.path.exists() {
+            return Ok(IndexMap::new());
+        }
+        let content = fs::read_to_string(&self.path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn write(&self, records: &IndexMap<String, TenantRecord>) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        // write to a temp file first so a crash never leaves a half written registry
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(records)?)?;
+        fs::rename(tmp, &self.path)?;
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TenantRegistry for FileRegistry {
+    fn load(&self) -> Result<Vec<TenantRecord>> {
+        let _guard = self.lock.lock().unwrap();
+        Ok(self.read()?.into_values().collect())
+    }
+
+    fn save(&self, record: &TenantRecord) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut records = self.read()?;
+        records.insert(record.host.clone(), record.clone());
+        self.write(&records)
+    }
+
+    fn remove(&self, host: &str) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut records = self.read()?;
+        if records.shift_remove(host).is_some() {
+            self.write(&records)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_registry_should_work() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let registry = FileRegistry::new(dir.path().join("registry.json"));
+        assert!(registry.load()?.is_empty());
+
+        let config = include_str!("../fixtures/config.yml");
+        let r1 = TenantRecord::new("localhost", "code1", config);
+        let r2 = TenantRecord::new("example.com", "code2", config);
+        registry.save(&r1)?;
+        registry.save(&r2)?;
+        assert_eq!(registry.load()?, vec![r1.clone(), r2.clone()]);
+
+        // saving the same host again replaces the old record
+        let r1 = TenantRecord::new("localhost", "code3", config);
+        registry.save(&r1)?;
+        assert_eq!(registry.load()?, vec![r1.clone(), r2]);
+
+        registry.remove("example.com")?;
+        assert_eq!(registry.load()?, vec![r1]);
+        Ok(())
+    }
+
+    #[test]
+    fn tenant_record_to_router_should_work() -> Result<()> {
+        let config = include_str!("../fixtures/config.yml");
+        let record = TenantRecord::new("localhost", "", config);
+        let tenant = record.to_router()?;
+        let router = tenant.router.load();
+        let m = router.match_it(axum::http::Method::GET, "/api/hello/1")?;
+        assert_eq!(m.value, "hello");
+        Ok(())
+    }
+}
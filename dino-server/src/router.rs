@@ -1,20 +1,52 @@
-use std::{ops::Deref, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, ops::Deref, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use arc_swap::ArcSwap;
-use axum::http::Method;
-use matchit::{Match, Router};
+use axum::{
+    body::Bytes,
+    http::{HeaderValue, Method, StatusCode},
+};
+use indexmap::IndexMap;
+use matchit::{Params, Router};
 
-use crate::{AppError, ProjectRoutes};
+use crate::{
+    check_routes, flatten_routes,
+    normalize::canonical_path,
+    openapi_document,
+    redirect::{pattern_params, same_host_path},
+    AppError, Authenticator, CompressionConfig, CorsPolicy, KeyedRateLimiter, Param,
+    ParamConstraint, PathConfig, ProjectConfig, ProjectRoutes, RateLimitConfig, Redirected,
+    Redirects, RequestValidator, ResponseCache, RouteGroup, RouteMethod, RouteOptions,
+    StaticConfig, StaticFiles, TenantMetrics, TenantQuota, TlsConfig,
+};
+
+/// Catch-all param holding the file path of a static route.
+pub(crate) const STATIC_PATH_PARAM: &str = "file";
+const STATIC_HANDLER: &str = "static";
 
 #[derive(Clone)]
 pub struct SwappableAppRouter {
     pub inner: Arc<ArcSwap<AppRouterInner>>,
+    pub metrics: Arc<TenantMetrics>,
 }
 
 pub struct AppRouterInner {
     pub code: String,
     pub router: Router<MethodRoute>,
+    pub quota: TenantQuota,
+    pub tls: Option<TlsConfig>,
+    pub cors: Option<Arc<CorsPolicy>>,
+    pub compression: Arc<CompressionConfig>,
+    pub rate_limit: Option<Arc<KeyedRateLimiter>>,
+    pub auth: Option<Arc<Authenticator>>,
+    pub cache: Option<Arc<ResponseCache>>,
+    pub redirects: Redirects,
+    /// path and body of the served OpenAPI document
+    pub openapi: Option<(String, Bytes)>,
+    pub paths: PathConfig,
+    // rate limiters by the methods and path of their route, the project's under an empty key,
+    // to keep their buckets across swaps
+    limiters: HashMap<String, Arc<KeyedRateLimiter>>,
 }
 
 #[derive(Clone)]
@@ -22,89 +54,350 @@ pub struct AppRouter(Arc<AppRouterInner>);
 
 #[derive(Debug, Clone, Default)]
 pub struct MethodRoute {
-    get: Option<String>,
-    head: Option<String>,
-    delete: Option<String>,
-    options: Option<String>,
-    patch: Option<String>,
-    post: Option<String>,
-    put: Option<String>,
-    trace: Option<String>,
-    connect: Option<String>,
+    pattern: String,
+    // in the order of the config
+    handlers: IndexMap<Method, RouteHandler>,
+    // serves the methods without a handler of their own
+    any: Option<RouteHandler>,
+    static_files: Option<Arc<StaticFiles>>,
+}
+
+/// A js handler configured on a route, with its overrides of the project settings.
+#[derive(Debug, Clone)]
+struct RouteHandler {
+    name: String,
+    cors: Option<Arc<CorsPolicy>>,
+    rate_limit: Option<Arc<KeyedRateLimiter>>,
+    auth: Option<Arc<Authenticator>>,
+    options: RouteOptions,
+    params: Vec<(String, ParamConstraint)>,
+    validator: Option<Arc<RequestValidator>>,
+}
+
+/// A handler matched for a request, along with the route pattern it was configured on.
+#[derive(Debug)]
+pub struct RouteMatch<'m, 'p> {
+    pub pattern: &'m str,
+    pub value: &'m str,
+    pub params: Params<'m, 'p>,
+    /// the route's own rate limit, the project's one applies as well
+    pub rate_limit: Option<&'m Arc<KeyedRateLimiter>>,
+    /// authentication of the route, or else of the project
+    pub auth: Option<&'m Arc<Authenticator>>,
+    /// set instead of a js handler for the routes of a `static` section
+    pub static_files: Option<&'m Arc<StaticFiles>>,
+    /// options of the js handler
+    pub options: Option<&'m RouteOptions>,
+    /// params converted to the type declared for them
+    pub typed_params: HashMap<String, Param>,
+    /// schemas the request has to match
+    pub validator: Option<&'m Arc<RequestValidator>>,
 }
 
 impl SwappableAppRouter {
-    pub fn try_new(code: impl Into<String>, routers: ProjectRoutes) -> Result<Self> {
-        let router = Self::get_router(routers)?;
-        let inner = AppRouterInner::new(code, router);
+    pub fn try_new(code: impl Into<String>, config: ProjectConfig) -> Result<Self> {
+        let inner = AppRouterInner::try_new(code, config)?;
         Ok(Self {
             inner: Arc::new(ArcSwap::from_pointee(inner)),
+            metrics: Default::default(),
         })
     }
-    pub fn swap(&self, code: impl Into<String>, routers: ProjectRoutes) -> Result<()> {
-        let router = Self::get_router(routers)?;
-        let inner = AppRouterInner::new(code, router);
+    pub fn swap(&self, code: impl Into<String>, config: ProjectConfig) -> Result<()> {
+        let previous = self.inner.load();
+        let inner =
+            AppRouterInner::try_new_with(code, config, Some(&previous)).inspect_err(|_| {
+                self.metrics.record_swap_failure();
+            })?;
         self.inner.store(Arc::new(inner));
+        self.metrics.record_swap();
         Ok(())
     }
     pub fn load(&self) -> AppRouter {
         AppRouter(self.inner.load_full())
     }
-    fn get_router(routers: ProjectRoutes) -> Result<Router<MethodRoute>> {
+    fn get_router(
+        routers: ProjectRoutes,
+        groups: Vec<RouteGroup>,
+        statics: &[StaticConfig],
+        schemas: &IndexMap<String, serde_json::Value>,
+        limiter: &mut impl FnMut(String, RateLimitConfig) -> Arc<KeyedRateLimiter>,
+    ) -> Result<Router<MethodRoute>> {
+        let routers = flatten_routes(routers, groups);
+        if let Some(diagnostic) = check_routes(&routers, None).into_iter().next() {
+            return Err(anyhow!("{}", diagnostic));
+        }
         let mut router = Router::new();
         for (path, routes) in routers {
-            let mut method_route = MethodRoute::default();
+            let mut method_route = MethodRoute {
+                pattern: path.clone(),
+                ..Default::default()
+            };
             for route in routes {
+                let handler = RouteHandler {
+                    name: route.handler,
+                    cors: route
+                        .cors
+                        .as_ref()
+                        .map(CorsPolicy::try_new)
+                        .transpose()?
+                        .map(Arc::new),
+                    rate_limit: route
+                        .rate_limit
+                        .map(|c| limiter(format!("{} {}", route.method, path), c)),
+                    auth: route
+                        .auth
+                        .as_ref()
+                        .map(Authenticator::try_new)
+                        .transpose()?
+                        .map(Arc::new),
+                    params: route
+                        .options
+                        .params
+                        .iter()
+                        .map(|(name, config)| {
+                            if !pattern_params(&path).any(|p| p == name) {
+                                return Err(anyhow!("param {} is not in path {}", name, path));
+                            }
+                            Ok((name.clone(), ParamConstraint::try_new(config)?))
+                        })
+                        .collect::<Result<_>>()?,
+                    validator: route
+                        .options
+                        .schema
+                        .as_ref()
+                        .map(|s| RequestValidator::try_new(s, schemas))
+                        .transpose()?
+                        .map(Arc::new),
+                    options: route.options,
+                };
                 match route.method {
-                    Method::GET => method_route.get = Some(route.handler),
-                    Method::HEAD => method_route.head = Some(route.handler),
-                    Method::DELETE => method_route.delete = Some(route.handler),
-                    Method::OPTIONS => method_route.options = Some(route.handler),
-                    Method::PATCH => method_route.patch = Some(route.handler),
-                    Method::POST => method_route.post = Some(route.handler),
-                    Method::PUT => method_route.put = Some(route.handler),
-                    Method::TRACE => method_route.trace = Some(route.handler),
-                    Method::CONNECT => method_route.connect = Some(route.handler),
-                    v => unreachable!("Unsupported method: {:?}", v),
+                    RouteMethod::Any => method_route.any = Some(handler),
+                    RouteMethod::Methods(methods) => {
+                        for method in methods {
+                            method_route.handlers.insert(method, handler.clone());
+                        }
+                    }
                 }
             }
             router.insert(path, method_route)?;
         }
+        for config in statics {
+            let files = Arc::new(StaticFiles::try_new(config)?);
+            let prefix = config.prefix.trim_end_matches('/');
+            let paths = [
+                format!("{}/*{}", prefix, STATIC_PATH_PARAM),
+                if prefix.is_empty() { "/" } else { prefix }.to_string(),
+            ];
+            for path in paths {
+                let method_route = MethodRoute {
+                    pattern: path.clone(),
+                    static_files: Some(files.clone()),
+                    ..Default::default()
+                };
+                router
+                    .insert(path.clone(), method_route)
+                    .map_err(|e| anyhow!("invalid static route {}: {}", path, e))?;
+            }
+        }
         Ok(router)
     }
 }
 
 impl AppRouter {
+    /// Redirect or route a path to its canonical form before it is matched, see
+    /// [`PathConfig`]. Strict rules leave the path as sent.
+    pub fn normalize(&self, path: &str, query: Option<&str>) -> Option<Redirected> {
+        let (canonical, redirect) = canonical_path(path, &self.paths);
+        if canonical == path {
+            return None;
+        }
+        if !redirect {
+            return Some(Redirected::Rewrite(canonical.into_owned()));
+        }
+        // strict duplicate slashes keep the leading `//` of the path, which must not leave the host
+        let path = same_host_path(&canonical);
+        let location = match query {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+        Some(Redirected::Redirect(
+            StatusCode::PERMANENT_REDIRECT,
+            location,
+        ))
+    }
+
+    /// Path a request is matched on once normalized and rewritten, or the status and `Location`
+    /// of the redirect to answer with instead.
+    pub fn resolve_path<'p>(
+        &self,
+        path: &'p str,
+        query: Option<&str>,
+    ) -> Result<Cow<'p, str>, (StatusCode, String)> {
+        let path = match self.normalize(path, query) {
+            Some(Redirected::Redirect(status, location)) => return Err((status, location)),
+            Some(Redirected::Rewrite(path)) => Cow::Owned(path),
+            None => Cow::Borrowed(path),
+        };
+        match self.redirects.resolve(&path, query) {
+            Some(Redirected::Redirect(status, location)) => Err((status, location)),
+            Some(Redirected::Rewrite(path)) => Ok(Cow::Owned(path)),
+            None => Ok(path),
+        }
+    }
+
     pub fn match_it<'m, 'p>(
         &'m self,
         method: Method,
         path: &'p str,
-    ) -> Result<Match<&str>, AppError>
+    ) -> Result<RouteMatch<'m, 'p>, AppError>
     where
         'p: 'm,
     {
         let Ok(ret) = self.router.at(path) else {
             return Err(AppError::RoutePathNotFound(path.to_string()));
         };
-        let s = match method {
-            Method::GET => ret.value.get.as_deref(),
-            Method::HEAD => ret.value.head.as_deref(),
-            Method::DELETE => ret.value.delete.as_deref(),
-            Method::OPTIONS => ret.value.options.as_deref(),
-            Method::PATCH => ret.value.patch.as_deref(),
-            Method::POST => ret.value.post.as_deref(),
-            Method::PUT => ret.value.put.as_deref(),
-            Method::TRACE => ret.value.trace.as_deref(),
-            Method::CONNECT => ret.value.connect.as_deref(),
-            _ => unreachable!(),
+        if let Some(files) = &ret.value.static_files {
+            if method != Method::GET && method != Method::HEAD {
+                return Err(AppError::RouteMethodNotAllowed {
+                    method,
+                    allowed: ret.value.allow(),
+                });
+            }
+            return Ok(RouteMatch {
+                pattern: &ret.value.pattern,
+                value: STATIC_HANDLER,
+                params: ret.params,
+                rate_limit: None,
+                auth: self.auth.as_ref(),
+                static_files: Some(files),
+                options: None,
+                typed_params: HashMap::new(),
+                validator: None,
+            });
         }
-        .ok_or_else(|| AppError::RouteMethodNotAllowed(method))?;
-        Ok(Match {
-            value: s,
+        let Some(handler) = ret.value.handler(&method) else {
+            return Err(AppError::RouteMethodNotAllowed {
+                method,
+                allowed: ret.value.allow(),
+            });
+        };
+        let typed_params = handler.check_params(&ret.params, path)?;
+        Ok(RouteMatch {
+            pattern: &ret.value.pattern,
+            value: &handler.name,
             params: ret.params,
+            rate_limit: handler.rate_limit.as_ref(),
+            auth: handler.auth.as_ref().or(self.auth.as_ref()),
+            static_files: None,
+            options: Some(&handler.options),
+            typed_params,
+            validator: handler.validator.as_ref(),
         })
     }
+
+    /// CORS policy of `method` on `path`: the route's own if it has one, otherwise the project's.
+    pub fn cors_policy(&self, method: &Method, path: &str) -> Option<&Arc<CorsPolicy>> {
+        self.router
+            .at(path)
+            .ok()
+            .and_then(|m| m.value.handler(method))
+            .and_then(|h| h.cors.as_ref())
+            .or(self.cors.as_ref())
+    }
+
+    /// Methods with a handler on `path`.
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        self.router
+            .at(path)
+            .map(|m| m.value.methods())
+            .unwrap_or_default()
+    }
 }
+
+impl MethodRoute {
+    // HEAD is served by the GET handler unless it has its own, ANY serves the other methods
+    fn handler(&self, method: &Method) -> Option<&RouteHandler> {
+        self.handlers
+            .get(method)
+            .or_else(|| {
+                (*method == Method::HEAD)
+                    .then(|| self.handlers.get(&Method::GET))
+                    .flatten()
+            })
+            .or(self.any.as_ref())
+    }
+
+    /// Methods listed in the `Allow` header: the configured ones, HEAD when GET is configured
+    /// and OPTIONS, which is always answered.
+    fn allow(&self) -> Vec<Method> {
+        let mut methods = self.methods();
+        if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+            methods.push(Method::HEAD);
+        }
+        if !methods.contains(&Method::OPTIONS) {
+            methods.push(Method::OPTIONS);
+        }
+        methods
+    }
+
+    fn methods(&self) -> Vec<Method> {
+        if self.static_files.is_some() {
+            return vec![Method::GET, Method::HEAD];
+        }
+        let mut methods = self.handlers.keys().cloned().collect::<Vec<_>>();
+        if self.any.is_some() {
+            let standard = [
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+                Method::OPTIONS,
+            ];
+            for method in standard {
+                if !methods.contains(&method) {
+                    methods.push(method);
+                }
+            }
+        }
+        methods
+    }
+}
+
+impl RouteHandler {
+    fn check_params(
+        &self,
+        params: &Params,
+        path: &str,
+    ) -> Result<HashMap<String, Param>, AppError> {
+        params
+            .iter()
+            .map(|(name, value)| {
+                let Some((_, constraint)) = self.params.iter().find(|(n, _)| n == name) else {
+                    return Ok((name.to_string(), Param::String(value.to_string())));
+                };
+                match constraint.check(value) {
+                    Ok(param) => Ok((name.to_string(), param)),
+                    Err(_) if constraint.status == StatusCode::NOT_FOUND => {
+                        Err(AppError::RoutePathNotFound(path.to_string()))
+                    }
+                    Err(reason) => Err(AppError::InvalidParam {
+                        name: name.to_string(),
+                        reason,
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Value of an `Allow` header listing `methods`.
+pub(crate) fn allow_header(methods: &[Method]) -> HeaderValue {
+    let methods = methods.iter().map(Method::as_str).collect::<Vec<_>>();
+    HeaderValue::from_str(&methods.join(", ")).unwrap_or(HeaderValue::from_static(""))
+}
+
 impl Deref for AppRouter {
     type Target = AppRouterInner;
     fn deref(&self) -> &Self::Target {
@@ -113,11 +406,68 @@ impl Deref for AppRouter {
 }
 
 impl AppRouterInner {
-    pub fn new(code: impl Into<String>, router: Router<MethodRoute>) -> Self {
-        Self {
+    pub fn try_new(code: impl Into<String>, config: ProjectConfig) -> Result<Self> {
+        Self::try_new_with(code, config, None)
+    }
+
+    // reuse the rate limiters and quota of `previous` when their config is unchanged
+    fn try_new_with(
+        code: impl Into<String>,
+        config: ProjectConfig,
+        previous: Option<&AppRouterInner>,
+    ) -> Result<Self> {
+        let openapi = config.openapi.serve.then(|| {
+            let doc = openapi_document(&config)?;
+            anyhow::Ok((config.openapi.path.clone(), Bytes::from(doc.to_string())))
+        });
+        let openapi = openapi.transpose()?;
+        let mut limiters = HashMap::new();
+        let mut limiter = |scope: String, config: RateLimitConfig| {
+            let limiter = previous
+                .and_then(|p| p.limiters.get(&scope))
+                .filter(|l| *l.config() == config)
+                .cloned()
+                .unwrap_or_else(|| Arc::new(KeyedRateLimiter::new(config)));
+            limiters.insert(scope, limiter.clone());
+            limiter
+        };
+        let router = SwappableAppRouter::get_router(
+            config.routes,
+            config.groups,
+            &config.static_files,
+            &config.schemas,
+            &mut limiter,
+        )?;
+        let rate_limit = config.rate_limit.map(|c| limiter(String::new(), c));
+        let quota = match previous {
+            Some(previous) => previous.quota.resize(config.limits),
+            None => TenantQuota::new(config.limits),
+        };
+        Ok(Self {
             code: code.into(),
             router,
-        }
+            quota,
+            tls: config.tls,
+            cors: config
+                .cors
+                .as_ref()
+                .map(CorsPolicy::try_new)
+                .transpose()?
+                .map(Arc::new),
+            compression: Arc::new(config.compression),
+            rate_limit,
+            auth: config
+                .auth
+                .as_ref()
+                .map(Authenticator::try_new)
+                .transpose()?
+                .map(Arc::new),
+            cache: config.cache.map(|c| Arc::new(ResponseCache::new(c))),
+            redirects: Redirects::try_new(&config.redirects, &config.rewrites)?,
+            openapi,
+            paths: config.paths,
+            limiters,
+        })
     }
 }
 
@@ -131,7 +481,7 @@ mod tests {
     fn app_router_match_should_work() {
         let config = include_str!("../fixtures/config.yml");
         let project_config: ProjectConfig = serde_yaml::from_str(config).unwrap();
-        let router = SwappableAppRouter::try_new("", project_config.routes).unwrap();
+        let router = SwappableAppRouter::try_new("", project_config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/1").unwrap();
         assert_eq!(m.value, "hello");
@@ -143,11 +493,174 @@ mod tests {
         assert_eq!(m.params.get("name"), Some("zzq"));
     }
 
+    #[test]
+    fn app_router_should_fall_back_to_get_for_head() {
+        let config = include_str!("../fixtures/config.yml");
+        let project_config: ProjectConfig = serde_yaml::from_str(config).unwrap();
+        let router = SwappableAppRouter::try_new("", project_config).unwrap();
+        let app_router = router.load();
+        let m = app_router.match_it(Method::HEAD, "/api/hello/1").unwrap();
+        assert_eq!(m.value, "hello");
+
+        let Err(AppError::RouteMethodNotAllowed { allowed, .. }) =
+            app_router.match_it(Method::PUT, "/api/hello/1")
+        else {
+            panic!("expect PUT to be rejected");
+        };
+        assert_eq!(allow_header(&allowed), "GET, POST, HEAD, OPTIONS");
+    }
+
+    #[test]
+    fn app_router_should_match_method_lists_and_any() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: methods
+            routes:
+              /api/items:
+                - method: [GET, POST]
+                  handler: items
+                - method: propfind
+                  handler: props
+              /api/proxy/*rest:
+                - method: DELETE
+                  handler: remove
+                - method: ANY
+                  handler: proxy
+            "#,
+        )?;
+        let router = SwappableAppRouter::try_new("", config)?;
+        let app_router = router.load();
+        assert_eq!(
+            app_router.match_it(Method::POST, "/api/items")?.value,
+            "items"
+        );
+        let propfind = Method::from_bytes(b"PROPFIND")?;
+        assert_eq!(app_router.match_it(propfind, "/api/items")?.value, "props");
+        assert!(app_router.match_it(Method::PUT, "/api/items").is_err());
+
+        assert_eq!(
+            app_router.match_it(Method::DELETE, "/api/proxy/a")?.value,
+            "remove"
+        );
+        let mkcol = Method::from_bytes(b"MKCOL")?;
+        assert_eq!(app_router.match_it(mkcol, "/api/proxy/a")?.value, "proxy");
+        Ok(())
+    }
+
+    #[test]
+    fn app_router_should_check_param_constraints() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: params
+            routes:
+              /api/users/:id/:tab:
+                - method: GET
+                  handler: user
+                  params:
+                    id: int
+                    tab: { enum: [posts, likes], status: 400 }
+            "#,
+        )?;
+        let router = SwappableAppRouter::try_new("", config)?;
+        let app_router = router.load();
+        let m = app_router.match_it(Method::GET, "/api/users/42/posts")?;
+        assert_eq!(m.typed_params["id"], Param::Int(42));
+        assert_eq!(m.typed_params["tab"], Param::String("posts".into()));
+        assert!(matches!(
+            app_router.match_it(Method::GET, "/api/users/abc/posts"),
+            Err(AppError::RoutePathNotFound(_))
+        ));
+        assert!(matches!(
+            app_router.match_it(Method::GET, "/api/users/42/shares"),
+            Err(AppError::InvalidParam { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn app_router_should_flatten_route_groups() -> Result<()> {
+        let config: ProjectConfig = serde_yaml::from_str(
+            r#"
+            name: groups
+            routes:
+              /health:
+                - method: GET
+                  handler: health
+            groups:
+              - prefix: /api/v1
+                timeout: 500
+                auth:
+                  type: api_key
+                  keys: { ci: abc }
+                routes:
+                  /:
+                    - method: GET
+                      handler: index
+                groups:
+                  - prefix: /tenants/:tenant
+                    params:
+                      tenant: int
+                    routes:
+                      /users:
+                        - method: GET
+                          handler: users
+                          timeout: 100
+            "#,
+        )?;
+        let router = SwappableAppRouter::try_new("", config)?;
+        let app_router = router.load();
+        assert_eq!(app_router.match_it(Method::GET, "/health")?.value, "health");
+        let m = app_router.match_it(Method::GET, "/api/v1")?;
+        assert_eq!(m.value, "index");
+        assert!(m.auth.is_some());
+        let m = app_router.match_it(Method::GET, "/api/v1/tenants/7/users")?;
+        assert_eq!(m.value, "users");
+        assert!(m.auth.is_some());
+        assert_eq!(m.typed_params["tenant"], Param::Int(7));
+        assert_eq!(m.options.and_then(|o| o.timeout), Some(100));
+
+        let source = r#"
+            name: merged
+            routes:
+              /api/users:
+                - method: GET
+                  handler: users
+            groups:
+              - prefix: /api
+                routes:
+                  /users:
+                    - method: POST
+                      handler: createUser
+                    - method: GET
+                      handler: listUsers
+            "#;
+        let config: ProjectConfig = serde_yaml::from_str(source)?;
+        let err = SwappableAppRouter::try_new("", config).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "GET /api/users is handled by both users and listUsers"
+        );
+
+        let mut config: ProjectConfig = serde_yaml::from_str(source)?;
+        config.groups[0].routes["/users"].pop();
+        let router = SwappableAppRouter::try_new("", config)?;
+        let app_router = router.load();
+        assert_eq!(
+            app_router.match_it(Method::GET, "/api/users")?.value,
+            "users"
+        );
+        assert_eq!(
+            app_router.match_it(Method::POST, "/api/users")?.value,
+            "createUser"
+        );
+        Ok(())
+    }
+
     #[test]
     fn app_router_swap_should_work() {
         let config = include_str!("../fixtures/config.yml");
         let project_config: ProjectConfig = serde_yaml::from_str(config).unwrap();
-        let router = SwappableAppRouter::try_new("", project_config.routes).unwrap();
+        let router = SwappableAppRouter::try_new("", project_config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::GET, "/api/hello/1").unwrap();
         assert_eq!(m.value, "hello");
@@ -155,11 +668,78 @@ mod tests {
 
         let new_config = include_str!("../fixtures/config1.yml");
         let new_project_config: ProjectConfig = serde_yaml::from_str(new_config).unwrap();
-        router.swap("", new_project_config.routes).unwrap();
+        router.swap("", new_project_config).unwrap();
         let app_router = router.load();
         let m = app_router.match_it(Method::POST, "/api/zzq/2").unwrap();
         assert_eq!(m.value, "handler2");
         assert_eq!(m.params.get("id"), Some("2"));
         assert_eq!(m.params.get("name"), Some("zzq"));
     }
+
+    #[test]
+    fn app_router_swap_should_keep_unchanged_rate_limits() -> Result<()> {
+        let config = |requests: u32| -> Result<ProjectConfig> {
+            let config = format!(
+                r#"
+                name: limits
+                rate_limit:
+                  requests: {requests}
+                routes:
+                  /api/hello/:id:
+                    - method: GET
+                      handler: hello
+                      rate_limit:
+                        requests: 1
+                "#
+            );
+            Ok(serde_yaml::from_str(&config)?)
+        };
+        let metrics = TenantMetrics::default();
+        let router = SwappableAppRouter::try_new("", config(1)?)?;
+        let app_router = router.load();
+        let route_limiter = app_router
+            .match_it(Method::GET, "/api/hello/1")?
+            .rate_limit
+            .unwrap()
+            .clone();
+        assert!(route_limiter.check("key", &metrics).is_ok());
+        assert!(app_router
+            .rate_limit
+            .as_ref()
+            .unwrap()
+            .check("key", &metrics)
+            .is_ok());
+
+        // the project limit changes, the route limit keeps its buckets
+        router.swap("", config(2)?)?;
+        let app_router = router.load();
+        let m = app_router.match_it(Method::GET, "/api/hello/1")?;
+        assert!(m.rate_limit.unwrap().check("key", &metrics).is_err());
+        let project_limiter = app_router.rate_limit.as_ref().unwrap();
+        assert!(project_limiter.check("key", &metrics).is_ok());
+        assert!(project_limiter.check("key", &metrics).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn app_router_should_match_static_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let config = format!(
+            "{}\nstatic:\n  - prefix: /assets\n    dir: {}\n",
+            include_str!("../fixtures/config.yml"),
+            dir.path().display()
+        );
+        let project_config: ProjectConfig = serde_yaml::from_str(&config)?;
+        let router = SwappableAppRouter::try_new("", project_config)?;
+        let app_router = router.load();
+        let m = app_router.match_it(Method::GET, "/assets/css/app.css")?;
+        assert!(m.static_files.is_some());
+        assert_eq!(m.params.get(STATIC_PATH_PARAM), Some("css/app.css"));
+        assert!(app_router
+            .match_it(Method::POST, "/assets/app.css")
+            .is_err());
+        let m = app_router.match_it(Method::GET, "/api/hello/1")?;
+        assert!(m.static_files.is_none());
+        Ok(())
+    }
 }
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+};
+use indexmap::IndexMap;
+use jsonschema::Validator;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{AppError, Param, RequestSchema, SchemaRef};
+
+/// A [`RequestSchema`] with its schemas compiled.
+#[derive(Debug)]
+pub struct RequestValidator {
+    query: Option<Schema>,
+    params: Option<Schema>,
+    headers: Option<Schema>,
+    body: Option<Schema>,
+}
+
+#[derive(Debug)]
+struct Schema {
+    validator: Validator,
+    // kept to convert string values to the type of their property
+    schema: Value,
+}
+
+/// A part of a request not matching its schema.
+#[derive(Debug, Clone, Serialize)]
+pub struct Violation {
+    /// `query`, `params`, `headers` or `body`
+    pub location: &'static str,
+    /// JSON pointer to the invalid value
+    pub path: String,
+    pub message: String,
+}
+
+impl RequestValidator {
+    pub fn try_new(config: &RequestSchema, schemas: &IndexMap<String, Value>) -> Result<Self> {
+        let compile = |schema: &Option<SchemaRef>| {
+            schema
+                .as_ref()
+                .map(|s| Schema::try_new(s, schemas))
+                .transpose()
+        };
+        Ok(Self {
+            query: compile(&config.query)?,
+            params: compile(&config.params)?,
+            headers: compile(&config.headers)?,
+            body: compile(&config.body)?,
+        })
+    }
+
+    /// Validate a request before its handler runs. Violations in the body get a 422, others
+    /// a 400.
+    pub fn validate(
+        &self,
+        headers: &HeaderMap,
+        query: &HashMap<String, String>,
+        params: &HashMap<String, Param>,
+        body: Option<&Bytes>,
+    ) -> Result<(), AppError> {
+        let mut violations = Vec::new();
+        if let Some(schema) = &self.query {
+            let query = query.iter().map(|(k, v)| (k.as_str(), v.as_str()));
+            schema.check("query", &schema.coerce(query), &mut violations);
+        }
+        if let Some(schema) = &self.params {
+            let params = params.iter().map(|(k, v)| (k.clone(), v.into())).collect();
+            schema.check("params", &Value::Object(params), &mut violations);
+        }
+        if let Some(schema) = &self.headers {
+            let headers = headers
+                .iter()
+                .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?)));
+            schema.check("headers", &schema.coerce(headers), &mut violations);
+        }
+        if !violations.is_empty() {
+            return Err(invalid(StatusCode::BAD_REQUEST, violations));
+        }
+
+        let Some(schema) = &self.body else {
+            return Ok(());
+        };
+        let body = match body.filter(|b| !b.is_empty()) {
+            Some(body) => serde_json::from_slice(body).map_err(|e| {
+                let violation = Violation {
+                    location: "body",
+                    path: String::new(),
+                    message: format!("invalid json: {}", e),
+                };
+                invalid(StatusCode::BAD_REQUEST, vec![violation])
+            })?,
+            None => Value::Null,
+        };
+        schema.check("body", &body, &mut violations);
+        if !violations.is_empty() {
+            return Err(invalid(StatusCode::UNPROCESSABLE_ENTITY, violations));
+        }
+        Ok(())
+    }
+}
+
+impl Schema {
+    fn try_new(schema: &SchemaRef, schemas: &IndexMap<String, Value>) -> Result<Self> {
+        let schema = match schema {
+            SchemaRef::Name(name) => schemas
+                .get(name)
+                .ok_or_else(|| anyhow!("schema {} is not defined in schemas", name))?,
+            SchemaRef::Inline(schema) => schema,
+        };
+        let validator = jsonschema::validator_for(schema).map_err(|e| anyhow!("{}", e))?;
+        Ok(Self {
+            validator,
+            schema: schema.clone(),
+        })
+    }
+
+    fn check(&self, location: &'static str, instance: &Value, violations: &mut Vec<Violation>) {
+        violations.extend(self.validator.iter_errors(instance).map(|e| Violation {
+            location,
+            path: e.instance_path.to_string(),
+            message: e.to_string(),
+        }));
+    }
+
+    // an object of the string values, converted to the type of their property if it has one
+    fn coerce<'a>(&self, values: impl Iterator<Item = (&'a str, &'a str)>) -> Value {
+        let values = values
+            .map(|(k, v)| {
+                let value = match self.schema["properties"][k]["type"].as_str() {
+                    Some("integer") => v.parse::<i64>().map(Value::from).ok(),
+                    Some("number") => v.parse::<f64>().ok().map(Value::from),
+                    Some("boolean") => v.parse::<bool>().map(Value::from).ok(),
+                    _ => None,
+                };
+                (k.to_string(), value.unwrap_or_else(|| v.into()))
+            })
+            .collect();
+        Value::Object(values)
+    }
+}
+
+fn invalid(status: StatusCode, violations: Vec<Violation>) -> AppError {
+    AppError::InvalidRequest { status, violations }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_validator_should_report_violations() -> Result<()> {
+        let schemas: IndexMap<String, Value> = serde_yaml::from_str(
+            r#"
+            User:
+              type: object
+              required: [name]
+              properties:
+                name: { type: string }
+            "#,
+        )?;
+        let config: RequestSchema = serde_yaml::from_str(
+            r#"
+            query:
+              type: object
+              properties:
+                page: { type: integer, minimum: 1 }
+            body: User
+            "#,
+        )?;
+        let validator = RequestValidator::try_new(&config, &schemas)?;
+        let headers = HeaderMap::new();
+        let params = HashMap::new();
+        let query = HashMap::from([("page".to_string(), "2".to_string())]);
+        let body = Bytes::from(r#"{"name":"alice"}"#);
+        validator.validate(&headers, &query, &params, Some(&body))?;
+
+        let query = HashMap::from([("page".to_string(), "0".to_string())]);
+        let Err(AppError::InvalidRequest { status, violations }) =
+            validator.validate(&headers, &query, &params, Some(&body))
+        else {
+            panic!("expect page 0 to be rejected");
+        };
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(violations[0].location, "query");
+        assert_eq!(violations[0].path, "/page");
+
+        let query = HashMap::new();
+        let body = Bytes::from(r#"{"age":1}"#);
+        let Err(AppError::InvalidRequest { status, violations }) =
+            validator.validate(&headers, &query, &params, Some(&body))
+        else {
+            panic!("expect a body without name to be rejected");
+        };
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(violations[0].location, "body");
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use std::time::UNIX_EPOCH;
+
+use anyhow::{anyhow, Result};
+use axum::{
+    body::Body,
+    http::{header, request::Parts, HeaderMap, HeaderValue, Request, Response, StatusCode},
+};
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+
+use crate::StaticConfig;
+
+/// Files of a `static` section, served with their content type, `Last-Modified`, a weak
+/// `ETag` and the configured `Cache-Control`. Range requests are supported.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    dir: ServeDir,
+    // index served for unknown paths
+    fallback: Option<ServeFile>,
+    cache_control: HeaderValue,
+}
+
+impl StaticFiles {
+    pub fn try_new(config: &StaticConfig) -> Result<Self> {
+        if !config.dir.is_dir() {
+            return Err(anyhow!(
+                "static dir {} does not exist",
+                config.dir.display()
+            ));
+        }
+        let fallback = config
+            .fallback
+            .as_ref()
+            .map(|file| ServeFile::new(config.dir.join(file)));
+        Ok(Self {
+            dir: ServeDir::new(&config.dir),
+            fallback,
+            cache_control: HeaderValue::from_str(&config.cache_control)?,
+        })
+    }
+
+    /// Serve `path`, relative to the static dir, for the request in `parts`.
+    pub async fn serve(&self, parts: &Parts, path: &str) -> Response<Body> {
+        let Ok(req) = file_request(parts, path) else {
+            return status(StatusCode::BAD_REQUEST);
+        };
+        let res = self
+            .dir
+            .clone()
+            .oneshot(req)
+            .await
+            .map(|r| r.map(Body::new));
+        let res = match (res, &self.fallback) {
+            (Ok(res), Some(fallback)) if res.status() == StatusCode::NOT_FOUND => {
+                let Ok(req) = file_request(parts, path) else {
+                    return status(StatusCode::BAD_REQUEST);
+                };
+                fallback
+                    .clone()
+                    .oneshot(req)
+                    .await
+                    .map(|r| r.map(Body::new))
+            }
+            (res, _) => res,
+        };
+        let Ok(mut res) = res;
+        if !(res.status().is_success() || res.status() == StatusCode::NOT_MODIFIED) {
+            return res;
+        }
+        res.headers_mut()
+            .insert(header::CACHE_CONTROL, self.cache_control.clone());
+        if res.status() != StatusCode::OK {
+            return res;
+        }
+        let Some(etag) = etag(res.headers()) else {
+            return res;
+        };
+        if if_none_match(&parts.headers, &etag) {
+            let mut not_modified = status(StatusCode::NOT_MODIFIED);
+            for name in [header::CACHE_CONTROL, header::LAST_MODIFIED] {
+                if let Some(v) = res.headers().get(&name) {
+                    not_modified.headers_mut().insert(name, v.clone());
+                }
+            }
+            res = not_modified;
+        }
+        res.headers_mut().insert(header::ETAG, etag);
+        res
+    }
+}
+
+fn file_request(parts: &Parts, path: &str) -> Result<Request<Body>> {
+    let mut req = Request::builder()
+        .method(parts.method.clone())
+        .uri(format!("/{}", path.trim_start_matches('/')))
+        .body(Body::empty())?;
+    *req.headers_mut() = parts.headers.clone();
+    Ok(req)
+}
+
+// derived from the size and the modification time, so no file has to be read
+fn etag(headers: &HeaderMap) -> Option<HeaderValue> {
+    let size: u64 = headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()?;
+    let modified = httpdate::parse_http_date(headers.get(header::LAST_MODIFIED)?.to_str().ok()?)
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    HeaderValue::from_str(&format!("W/\"{:x}-{:x}\"", size, modified)).ok()
+}
+
+fn if_none_match(headers: &HeaderMap, etag: &HeaderValue) -> bool {
+    let etag = weak(etag.to_str().unwrap_or_default());
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim() == "*" || weak(v.trim()) == etag)
+}
+
+fn weak(etag: &str) -> &str {
+    etag.strip_prefix("W/").unwrap_or(etag)
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut res = Response::new(Body::empty());
+    *res.status_mut() = code;
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    async fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> Response<Body> {
+        let mut req = Request::get(format!("/assets/{}", path));
+        for (k, v) in headers {
+            req = req.header(*k, *v);
+        }
+        let (parts, _) = req.body(()).unwrap().into_parts();
+        files.serve(&parts, path).await
+    }
+
+    #[tokio::test]
+    async fn static_files_should_serve_with_etag_and_range() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(dir.path().join("app.js"), "console.log('hello')")?;
+        fs::write(dir.path().join("index.html"), "<html></html>")?;
+        let config: StaticConfig = serde_yaml::from_str(&format!(
+            "prefix: /assets\ndir: {}\nfallback: index.html",
+            dir.path().display()
+        ))?;
+        let files = StaticFiles::try_new(&config)?;
+
+        let res = get(&files, "app.js", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/javascript");
+        assert_eq!(res.headers()[header::CACHE_CONTROL], "public, max-age=3600");
+        let etag = res.headers()[header::ETAG].to_str()?.to_string();
+
+        let res = get(&files, "app.js", &[("if-none-match", &etag)]).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let res = get(&files, "app.js", &[("range", "bytes=0-6")]).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        assert_eq!(body, "console");
+
+        let res = get(&files, "some/page", &[]).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/html");
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::Result;
+use opentelemetry::{global, trace::TracerProvider as _, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Tracer, TracerProvider},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+const SERVICE_NAME: &str = "dino-server";
+
+/// Exports request spans to an OTLP collector over http.
+pub struct Telemetry {
+    provider: TracerProvider,
+    tracer: Tracer,
+}
+
+impl Telemetry {
+    /// `endpoint` is the OTLP/http traces endpoint, e.g. `http://localhost:4318/v1/traces`.
+    pub fn try_new(endpoint: &str) -> Result<Self> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(endpoint)
+            .build()?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)]))
+            .build();
+        Ok(Self::new(provider))
+    }
+
+    pub fn new(provider: TracerProvider) -> Self {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = provider.tracer(SERVICE_NAME);
+        Self { provider, tracer }
+    }
+
+    /// A tracing layer turning spans into OpenTelemetry spans.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.tracer.clone())
+    }
+
+    /// Flush pending spans, call it before the process exits.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!("Failed to shutdown tracer provider: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{test_utils::test_app, ProjectConfig};
+    use axum::{
+        body::{Body, Bytes},
+        http::Request,
+        routing::post,
+        Router,
+    };
+    use tokio::net::TcpListener;
+    use tower::ServiceExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn telemetry_should_export_request_spans() -> Result<()> {
+        // stub collector keeping the bodies of the export requests
+        let exported = Arc::new(Mutex::new(Vec::new()));
+        let collector = Router::new().route(
+            "/v1/traces",
+            post({
+                let exported = exported.clone();
+                move |body: Bytes| async move { exported.lock().unwrap().extend(body) }
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let endpoint = format!("http://{}/v1/traces", listener.local_addr()?);
+        tokio::spawn(async move { axum::serve(listener, collector).await });
+
+        let telemetry = Telemetry::try_new(&endpoint)?;
+        // global, as the run_js span is exited on a thread of the blocking pool
+        let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+        tracing::subscriber::set_global_default(subscriber)?;
+
+        let code = r#"
+        (function(){async function hello(req){return{status:200,headers:{},body:req.trace_headers.traceparent};}return{hello:hello};})();"#;
+        let config: ProjectConfig = serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+        let app = test_app(code, config)?;
+        let req = Request::get("/api/hello/1")
+            .header("host", "localhost")
+            .header(
+                "traceparent",
+                "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+            )
+            .body(Body::empty())?;
+        let res = app.oneshot(req).await?;
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX).await?;
+        // the handler gets the context of the request span to forward
+        assert!(body.starts_with(b"00-0af7651916cd43dd8448eb211c80319c-"));
+
+        tokio::task::spawn_blocking(move || telemetry.shutdown()).await?;
+        // names and ids are stored as is in the protobuf payload
+        let exported = exported.lock().unwrap();
+        let contains = |needle: &[u8]| exported.windows(needle.len()).any(|w| w == needle);
+        for name in [
+            "GET /api/hello/:id",
+            "http.route",
+            "match_route",
+            "acquire_worker",
+            "run_js",
+        ] {
+            assert!(contains(name.as_bytes()), "span {} not exported", name);
+        }
+        // named after the route, not the path
+        assert!(!contains(b"GET /api/hello/1"));
+        // trace id of the incoming traceparent
+        assert!(contains(&[
+            0x0a, 0xf7, 0x65, 0x19, 0x16, 0xcd, 0x43, 0xdd, 0x84, 0x48, 0xeb, 0x21, 0x1c, 0x80,
+            0x31, 0x9c
+        ]));
+        Ok(())
+    }
+}
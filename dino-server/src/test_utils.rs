@@ -0,0 +1,24 @@
+use anyhow::Result;
+use axum::Router;
+use dashmap::DashMap;
+
+use crate::{app, AppState, ProjectConfig, SwappableAppRouter};
+
+/// A bundle whose `hello` handler answers `hello`.
+pub(crate) const HELLO_CODE: &str = r#"
+(function(){async function hello(req){return{status:200,headers:{},body:"hello"};}return{hello:hello};})();"#;
+
+/// State serving a single `localhost` tenant running `code` with `config`.
+pub(crate) fn test_state(code: &str, config: ProjectConfig) -> Result<AppState> {
+    let state = AppState::new(DashMap::new());
+    state.router.insert(
+        "localhost".to_string(),
+        SwappableAppRouter::try_new(code, config)?,
+    );
+    Ok(state)
+}
+
+/// The app of [`test_state`].
+pub(crate) fn test_app(code: &str, config: ProjectConfig) -> Result<Router> {
+    Ok(app(test_state(code, config)?))
+}
@@ -0,0 +1,255 @@
+use std::{fmt, fs, io::BufReader, path::Path, sync::Arc, time::SystemTime};
+
+use anyhow::{anyhow, Result};
+use axum::Router;
+use dashmap::DashMap;
+use rustls::{
+    crypto::ring::{default_provider, sign::any_supported_type},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::warn;
+
+use crate::{
+    listener::{accept, serve_connection},
+    AppState, TlsConfig,
+};
+
+/// Picks the certificate of the tenant matching the SNI server name. Certificates are
+/// loaded lazily and reloaded whenever the files on disk change.
+pub struct TenantCertResolver {
+    state: AppState,
+    cache: DashMap<String, CachedCert>,
+}
+
+struct CachedCert {
+    config: TlsConfig,
+    modified: (SystemTime, SystemTime),
+    key: Arc<CertifiedKey>,
+}
+
+impl TenantCertResolver {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            state,
+            cache: DashMap::new(),
+        }
+    }
+
+    fn get_cert(&self, host: &str) -> Result<Option<Arc<CertifiedKey>>> {
+        let Some(tls) = self
+            .state
+            .router
+            .get(host)
+            .and_then(|r| r.load().tls.clone())
+        else {
+            return Ok(None);
+        };
+        let modified = (modified_at(&tls.cert)?, modified_at(&tls.key)?);
+        if let Some(cached) = self.cache.get(host) {
+            if cached.config == tls && cached.modified == modified {
+                return Ok(Some(cached.key.clone()));
+            }
+        }
+        let key = Arc::new(load_certified_key(&tls)?);
+        self.cache.insert(
+            host.to_string(),
+            CachedCert {
+                config: tls,
+                modified,
+                key: key.clone(),
+            },
+        );
+        Ok(Some(key))
+    }
+}
+
+impl ResolvesServerCert for TenantCertResolver {
+    fn resolve(&self, hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        let host = hello.server_name()?;
+        match self.get_cert(host) {
+            Ok(key) => key,
+            Err(e) => {
+                warn!("Failed to load certificate for {}: {:?}", host, e);
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Debug for TenantCertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TenantCertResolver").finish_non_exhaustive()
+    }
+}
+
+pub(crate) fn tls_acceptor(state: AppState) -> Result<TlsAcceptor> {
+    let mut config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(TenantCertResolver::new(state)));
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+pub(crate) async fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    app: Router,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let tracker = TaskTracker::new();
+    loop {
+        let (stream, peer) = tokio::select! {
+            ret = accept(|| listener.accept()) => ret,
+            _ = shutdown.cancelled() => break,
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+        let shutdown = shutdown.clone();
+        tracker.spawn(async move {
+            match acceptor.accept(stream).await {
+                Ok(stream) => serve_connection(stream, Some(peer), app, shutdown).await,
+                Err(e) => warn!("TLS handshake with {} failed: {:?}", peer, e),
+            }
+        });
+    }
+    tracker.close();
+    tracker.wait().await;
+    Ok(())
+}
+
+fn modified_at(path: &Path) -> Result<SystemTime> {
+    Ok(fs::metadata(path)?.modified()?)
+}
+
+fn load_certified_key(tls: &TlsConfig) -> Result<CertifiedKey> {
+    let mut reader = BufReader::new(fs::File::open(&tls.cert)?);
+    let certs = rustls_pemfile::certs(&mut reader).collect::<Result<Vec<_>, _>>()?;
+    let mut reader = BufReader::new(fs::File::open(&tls.key)?);
+    let key = rustls_pemfile::private_key(&mut reader)?
+        .ok_or_else(|| anyhow!("no private key found in {}", tls.key.display()))?;
+    Ok(CertifiedKey::new(certs, any_supported_type(&key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProjectConfig, SwappableAppRouter};
+    use rcgen::CertifiedKey as GeneratedCert;
+    use rustls::{pki_types::ServerName, ClientConfig, RootCertStore};
+    use std::net::SocketAddr;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_rustls::TlsConnector;
+
+    #[tokio::test]
+    async fn tls_server_should_select_cert_by_sni() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (addr, certs) = start_tls_server(dir.path(), &["localhost"]).await?;
+
+        let res = get(addr, "localhost", &certs[0]).await?;
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        assert!(res.ends_with("localhost"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_server_should_serve_each_tenant_its_own_cert() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (addr, certs) = start_tls_server(dir.path(), &["a.test", "b.test"]).await?;
+
+        let res = get(addr, "a.test", &certs[0]).await?;
+        assert!(res.ends_with("a.test"));
+        let res = get(addr, "b.test", &certs[1]).await?;
+        assert!(res.ends_with("b.test"));
+        // b.test must not be answered with the certificate of a.test
+        assert!(get(addr, "b.test", &certs[0]).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tls_server_should_reject_unknown_sni() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let (addr, certs) = start_tls_server(dir.path(), &["localhost"]).await?;
+
+        assert!(get(addr, "unknown.test", &certs[0]).await.is_err());
+        // a failed handshake does not affect the other connections
+        let res = get(addr, "localhost", &certs[0]).await?;
+        assert!(res.starts_with("HTTP/1.1 200 OK"));
+        Ok(())
+    }
+
+    /// Serves one tenant per host, each with a self signed certificate for its name and
+    /// a handler answering the host.
+    async fn start_tls_server(
+        dir: &Path,
+        hosts: &[&str],
+    ) -> Result<(SocketAddr, Vec<GeneratedCert>)> {
+        let state = AppState::new(DashMap::new());
+        let mut certs = Vec::new();
+        for host in hosts {
+            let cert = rcgen::generate_simple_self_signed(vec![host.to_string()])?;
+            let cert_path = dir.join(format!("{}.crt", host));
+            let key_path = dir.join(format!("{}.key", host));
+            fs::write(&cert_path, cert.cert.pem())?;
+            fs::write(&key_path, cert.key_pair.serialize_pem())?;
+
+            let mut config: ProjectConfig =
+                serde_yaml::from_str(include_str!("../fixtures/config.yml"))?;
+            config.tls = Some(TlsConfig {
+                cert: cert_path,
+                key: key_path,
+            });
+            let code = format!(
+                r#"(function(){{async function hello(req){{return{{status:200,headers:{{}},body:"{}"}};}}return{{hello:hello}};}})();"#,
+                host
+            );
+            state.router.insert(
+                host.to_string(),
+                SwappableAppRouter::try_new(&code, config)?,
+            );
+            certs.push(cert);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let acceptor = tls_acceptor(state.clone())?;
+        tokio::spawn(serve_tls(
+            listener,
+            acceptor,
+            crate::app(state),
+            CancellationToken::new(),
+        ));
+        Ok((addr, certs))
+    }
+
+    /// Requests `/api/hello/1` from `host` over TLS, trusting only `cert`.
+    async fn get(addr: SocketAddr, host: &str, cert: &GeneratedCert) -> Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone())?;
+        let client = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = TlsConnector::from(Arc::new(client));
+        let stream = TcpStream::connect(addr).await?;
+        let mut stream = connector
+            .connect(ServerName::try_from(host.to_string())?, stream)
+            .await?;
+        let req = format!(
+            "GET /api/hello/1 HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            host
+        );
+        stream.write_all(req.as_bytes()).await?;
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).await?;
+        Ok(buf)
+    }
+}
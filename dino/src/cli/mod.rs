@@ -1,12 +1,14 @@
 mod build;
 mod init;
+mod openapi;
 mod run;
 
 pub use build::BuildOpts;
 use clap::Parser;
 use enum_dispatch::enum_dispatch;
 pub use init::InitOpts;
-pub use run::RunOpts;
+pub use openapi::OpenApiOpts;
+pub use run::{LogFormat, RunOpts};
 
 // rcli csv -i input.csv -o output.csv --header -d ','
 #[derive(Debug, Parser)]
@@ -24,4 +26,6 @@ pub enum SubCommand {
     Build(BuildOpts),
     #[command(name = "run", about = "Run user's dino project")]
     Run(RunOpts),
+    #[command(name = "openapi", about = "Generate the OpenAPI document of the project")]
+    OpenApi(OpenApiOpts),
 }
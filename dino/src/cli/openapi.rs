@@ -0,0 +1,28 @@
+use std::{fs, path::PathBuf};
+
+use clap::Parser;
+use dino_server::{openapi_document, ProjectConfig};
+
+use crate::CmdExecutor;
+
+#[derive(Debug, Parser)]
+pub struct OpenApiOpts {
+    // file to write the document to, printed to stdout when omitted
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+impl CmdExecutor for OpenApiOpts {
+    async fn execute(&self) -> anyhow::Result<()> {
+        let config = ProjectConfig::load("config.yml")?;
+        let doc = format!("{:#}", openapi_document(&config)?);
+        match &self.output {
+            Some(output) => {
+                fs::write(output, doc)?;
+                eprintln!("Wrote OpenAPI document to {}", output.display());
+            }
+            None => println!("{}", doc),
+        }
+        Ok(())
+    }
+}
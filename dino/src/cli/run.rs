@@ -1,7 +1,10 @@
-use std::{fs, path::Path, time::Duration};
+use std::{fs, net::SocketAddr, path::Path, sync::Arc, time::Duration};
 
-use clap::Parser;
-use dino_server::{start_server, ProjectConfig, SwappableAppRouter, TenentRouter};
+use clap::{Parser, ValueEnum};
+use dino_server::{
+    start_server, AccessLogConfig, FileRegistry, ListenerConfig, ProjectConfig, ServerConfig,
+    SwappableAppRouter, Telemetry, TenantRecord, TenantRegistry, TenentRouter,
+};
 use notify::RecursiveMode;
 use notify_debouncer_mini::{new_debouncer, DebounceEventResult};
 use tokio::sync::mpsc::channel;
@@ -11,40 +14,113 @@ use tracing_subscriber::{
     fmt::Layer, layer::SubscriberExt as _, util::SubscriberInitExt as _, Layer as _,
 };
 
-use crate::{build_project, CmdExecutor};
+use crate::{build_project, static_dirs, use_built_static_dirs, CmdExecutor, BUILD_DIR};
 
 const MONITOR_FS_INTERVAL: Duration = Duration::from_secs(2);
+const HOST: &str = "localhost";
 
 #[derive(Debug, Parser)]
 pub struct RunOpts {
     // port to listen
     #[arg(short, long, default_value = "3000")]
     pub port: u16,
+    // port to listen for https, certificates are configured in config.yml
+    #[arg(long)]
+    pub tls_port: Option<u16>,
+    // addresses to listen, e.g. 127.0.0.1:3000, [::1]:3000, https://0.0.0.0:3443 or unix:/tmp/dino.sock.
+    // --port and --tls-port are ignored when given
+    #[arg(short, long)]
+    pub listen: Vec<ListenerConfig>,
+    // seconds to wait for in-flight requests on shutdown
+    #[arg(long, default_value = "30")]
+    pub drain_timeout: u64,
+    // fraction of requests written to the access log, 0 disables it
+    #[arg(long, default_value = "1.0")]
+    pub access_log_sample_rate: f64,
+    // address of the admin listener serving prometheus metrics at /metrics, e.g. 127.0.0.1:9090
+    #[arg(long)]
+    pub admin_listen: Option<SocketAddr>,
+    // format of the log output
+    #[arg(long, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+    // OTLP/http endpoint to export traces to, e.g. http://localhost:4318/v1/traces
+    #[arg(long)]
+    pub otlp_endpoint: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
 }
 
 impl CmdExecutor for RunOpts {
     async fn execute(&self) -> anyhow::Result<()> {
-        let layer = Layer::new().with_filter(LevelFilter::INFO);
-        tracing_subscriber::registry().with(layer).init();
+        let layer = match self.log_format {
+            LogFormat::Text => Layer::new().with_filter(LevelFilter::INFO).boxed(),
+            LogFormat::Json => Layer::new().json().with_filter(LevelFilter::INFO).boxed(),
+        };
+        let telemetry = self
+            .otlp_endpoint
+            .as_deref()
+            .map(Telemetry::try_new)
+            .transpose()?;
+        tracing_subscriber::registry()
+            .with(layer)
+            .with(telemetry.as_ref().map(|t| t.layer()))
+            .init();
 
-        let (code, config) = get_code_and_config()?;
-        let router = SwappableAppRouter::try_new(&code, config.routes)?;
-        let routers = vec![TenentRouter::new("localhost", router.clone())];
+        let registry = Arc::new(FileRegistry::new(format!("{}/registry.json", BUILD_DIR)));
+        let (record, config) = get_code_and_config()?;
+        let router = SwappableAppRouter::try_new(&record.code, config)?;
+        save_record(registry.as_ref(), &record);
+        let routers = vec![TenentRouter::new(HOST, router.clone())];
 
-        tokio::spawn(async_watch(".", router));
-        start_server(self.port, routers).await?;
+        let watcher = tokio::spawn(async_watch(".", router, registry.clone()));
+        let mut server_config = if self.listen.is_empty() {
+            ServerConfig::from_ports(self.port, self.tls_port)
+        } else {
+            ServerConfig::new(self.listen.clone())
+        };
+        server_config.drain_timeout = Duration::from_secs(self.drain_timeout);
+        server_config.admin = self.admin_listen;
+        server_config.access_log = (self.access_log_sample_rate > 0.0).then_some(AccessLogConfig {
+            sample_rate: self.access_log_sample_rate,
+        });
+        start_server(server_config, routers, registry.as_ref()).await?;
+        watcher.abort();
+        info!("File watcher stopped");
+        if let Some(telemetry) = telemetry {
+            telemetry.shutdown();
+        }
         Ok(())
     }
 }
 
-fn get_code_and_config() -> anyhow::Result<(String, ProjectConfig)> {
+fn get_code_and_config() -> anyhow::Result<(TenantRecord, ProjectConfig)> {
     let filename = build_project(".")?;
     let code = fs::read_to_string(&filename)?;
-    let config = ProjectConfig::load(filename.replace(".mjs", ".yml"))?;
-    Ok((code, config))
+    let content = fs::read_to_string(filename.replace(".mjs", ".yml"))?;
+    // the record keeps the config as written, so a restored tenant serves the static dirs of the
+    // project rather than the copies of the build
+    let record = TenantRecord::new(HOST, code, content);
+    let mut config = record.project_config()?;
+    use_built_static_dirs(&filename, &mut config)?;
+    Ok((record, config))
 }
 
-async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow::Result<()> {
+// remember a deployment that is being served, so it survives a restart
+fn save_record(registry: &dyn TenantRegistry, record: &TenantRecord) {
+    if let Err(e) = registry.save(record) {
+        warn!("Failed to save tenant {} to the registry: {:?}", record.host, e);
+    }
+}
+
+async fn async_watch(
+    p: impl AsRef<Path>,
+    router: SwappableAppRouter,
+    registry: Arc<FileRegistry>,
+) -> anyhow::Result<()> {
     let (tx, rx) = channel(1);
     let mut debouncer = new_debouncer(MONITOR_FS_INTERVAL, move |res: DebounceEventResult| {
         if let Err(e) = tx.blocking_send(res) {
@@ -60,19 +136,36 @@ async fn async_watch(p: impl AsRef<Path>, router: SwappableAppRouter) -> anyhow:
         match ret {
             Ok(events) => {
                 let mut need_swap = false;
-                // config.yml change, or any .ts or .mjs file change
+                let static_dirs = static_dirs()
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|dir| dir.canonicalize().ok())
+                    .collect::<Vec<_>>();
+                // config.yml change, any .ts or .mjs file change, or any static file change
                 for event in events {
                     let path = event.path;
                     let ext = path.extension().unwrap_or_default();
-                    if path.ends_with("config.yml") || ext == "ts" || ext == "mjs" {
+                    let is_static = path
+                        .canonicalize()
+                        .is_ok_and(|p| static_dirs.iter().any(|dir| p.starts_with(dir)));
+                    if path.ends_with("config.yml") || ext == "ts" || ext == "mjs" || is_static {
                         info!("file change: {:?}", path);
                         need_swap = true;
                         break;
                     }
                 }
                 if need_swap {
-                    let (code, config) = get_code_and_config()?;
-                    router.swap(code, config.routes)?;
+                    // a broken build must not stop the watcher, keep serving the old code instead
+                    match get_code_and_config() {
+                        Ok((record, config)) => match router.swap(record.code.clone(), config) {
+                            Ok(()) => save_record(registry.as_ref(), &record),
+                            Err(e) => warn!("Failed to swap router: {:?}", e),
+                        },
+                        Err(e) => {
+                            router.metrics.record_swap_failure();
+                            warn!("Failed to build project: {:?}", e);
+                        }
+                    }
                 }
             }
             Err(e) => warn!("watch error: {:?}", e),
@@ -1,10 +1,10 @@
 use anyhow::Result;
 use bundler::run_bundle;
+use dino_server::ProjectConfig;
 use glob::{glob, GlobError};
 use std::{
     collections::BTreeSet,
     fs::{self, File},
-    io,
     path::{Path, PathBuf},
 };
 
@@ -32,28 +32,105 @@ pub(crate) fn calc_hash_for_files(dir: &str, exts: &[&str], len: usize) -> Resul
     Ok(ret)
 }
 
-pub(crate) fn calc_project_hash(dir: &str) -> Result<String> {
-    calc_hash_for_files(dir, &["ts", "js", "json"], 16)
+// get all files in a directory, recursively
+pub(crate) fn get_files(dir: &Path) -> Result<BTreeSet<PathBuf>> {
+    let pattern = format!("{}/**/*", dir.display());
+    let files = glob(&pattern)?.collect::<Result<BTreeSet<PathBuf>, GlobError>>()?;
+    Ok(files.into_iter().filter(|p| p.is_file()).collect())
 }
 
+pub(crate) fn calc_project_hash(dir: &str, static_dirs: &[PathBuf]) -> Result<String> {
+    let hash = calc_hash_for_files(dir, &["ts", "js", "json"], 16)?;
+    if static_dirs.is_empty() {
+        return Ok(hash);
+    }
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(hash.as_bytes());
+    for dir in static_dirs {
+        for file in get_files(dir)? {
+            // renaming a static file changes its url, so the name is part of the hash
+            hasher.update(file.to_string_lossy().as_bytes());
+            hasher.update_reader(File::open(file)?)?;
+        }
+    }
+    let mut ret = hasher.finalize().to_string();
+    ret.truncate(16);
+    Ok(ret)
+}
+
+/// Directories of the `static` section of the project's config.yml.
+pub(crate) fn static_dirs() -> Result<Vec<PathBuf>> {
+    let config = ProjectConfig::load("config.yml")?;
+    Ok(config.static_files.into_iter().map(|s| s.dir).collect())
+}
+
+/// Bundle the project into `BUILD_DIR`, named after its hash: the code in `<hash>.mjs`, the
+/// config.yml as is in `<hash>.yml` and a copy of each static dir in `<hash>/static/<index>`.
+/// Everything is written to a temp dir first, so a failed build leaves nothing behind.
 pub(crate) fn build_project(dir: &str) -> Result<String> {
-    let hash = calc_project_hash(dir)?;
+    let static_dirs = static_dirs()?;
+    let hash = calc_project_hash(dir, &static_dirs)?;
     fs::create_dir_all(BUILD_DIR)?;
     let filename = format!("{}/{}.mjs", BUILD_DIR, hash);
-    let config = format!("{}/{}.yml", BUILD_DIR, hash);
     let dst = Path::new(&filename);
 
     if dst.exists() {
         return Ok(filename);
     }
 
+    let tmp = Path::new(BUILD_DIR).join(format!("{}.tmp", hash));
+    // left over by a build that was interrupted
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    fs::create_dir_all(&tmp)?;
+    let ret = build_into(&tmp, &static_dirs).and_then(|_| {
+        if !static_dirs.is_empty() {
+            let out = Path::new(BUILD_DIR).join(&hash);
+            if out.exists() {
+                fs::remove_dir_all(&out)?;
+            }
+            fs::create_dir_all(&out)?;
+            fs::rename(tmp.join("static"), out.join("static"))?;
+        }
+        fs::rename(tmp.join("config.yml"), format!("{}/{}.yml", BUILD_DIR, hash))?;
+        // the code is moved last, as its presence marks a complete build
+        fs::rename(tmp.join("main.mjs"), dst)?;
+        Ok(())
+    });
+    let cleanup = fs::remove_dir_all(&tmp);
+    ret?;
+    cleanup?;
+    Ok(filename)
+}
+
+// write the code, config and static files of a build to `out`
+fn build_into(out: &Path, static_dirs: &[PathBuf]) -> Result<()> {
     let content = run_bundle("main.ts", &Default::default())?;
-    std::fs::write(dst, content)?;
+    fs::write(out.join("main.mjs"), content)?;
+    // as written, so the lines of the diagnostics match the project's config.yml
+    fs::copy("config.yml", out.join("config.yml"))?;
+    for (i, src) in static_dirs.iter().enumerate() {
+        let dst = out.join("static").join(i.to_string());
+        fs::create_dir_all(&dst)?;
+        for file in get_files(src)? {
+            let target = dst.join(file.strip_prefix(src)?);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&file, &target)?;
+        }
+    }
+    Ok(())
+}
 
-    let mut dst = File::create(&config)?;
-    let mut src = File::open("config.yml")?;
-    io::copy(&mut src, &mut dst)?;
-    Ok(filename)
+/// Point the static dirs of the config of a build at the copies made by [`build_project`].
+pub(crate) fn use_built_static_dirs(filename: &str, config: &mut ProjectConfig) -> Result<()> {
+    let out = Path::new(filename).with_extension("");
+    for (i, entry) in config.static_files.iter_mut().enumerate() {
+        entry.dir = fs::canonicalize(out.join("static").join(i.to_string()))?;
+    }
+    Ok(())
 }
 
 #[cfg(test)]